@@ -1,4 +1,8 @@
 pub mod build;
 pub mod clean;
+pub mod coverage;
 pub mod new;
+pub mod pgo;
 pub mod run;
+pub mod test;
+pub mod why;
@@ -10,14 +10,102 @@ use clap::Parser;
 use serde::{Deserialize, Serialize};
 use xshell::{Shell, cmd};
 
-use crate::config::{Args, BuildConfig, Config, TargetConfig, TargetLanguage, TargetType};
+use crate::config::{
+    Args, BuildConfig, Config, TargetConfig, TargetLanguage, TargetType, ToolchainConfig,
+};
+
+use checks::CheckResults;
+use explain::{RebuildReason, modified_time, parse_depfile};
+use pgo::PgoPhase;
+use probe::{CompilerFamily, ProbeCache};
+use timings::{StepKind, Timings};
 
+pub mod build_info;
+pub mod checks;
+pub mod coverage;
 pub mod deps;
+pub mod explain;
+pub mod generate;
+pub mod lto;
+pub mod modules;
+pub mod object_cache;
+pub mod pch;
+pub mod pgo;
+pub mod probe;
+pub mod reproducible;
+pub mod resources;
+pub mod sanitize;
+pub mod strip;
+pub mod time_trace;
+pub mod timings;
+pub mod unity;
 
 #[derive(Debug, Clone, Parser)]
 pub struct BuildOpts {
     #[arg(short, long, default_value = "debug")]
     pub profile: String,
+
+    /// Toolchain to build with, overriding the one selected by the profile
+    #[arg(short, long)]
+    pub toolchain: Option<String>,
+
+    /// Build with the given sanitizers (e.g. `address,undefined`), in a separate output directory
+    #[arg(long, value_delimiter = ',', value_name = "SANITIZERS")]
+    pub sanitize: Vec<String>,
+
+    /// Compiler launcher to cache compilations with (e.g. `ccache`), overriding the profile's
+    /// `compiler_launcher`
+    #[arg(long, value_name = "LAUNCHER")]
+    pub cache: Option<String>,
+
+    /// Report why each object file or artifact is being rebuilt
+    #[arg(long, default_value_t = false)]
+    pub explain: bool,
+
+    /// Time every build step, print the slowest ones and write a timing report to the build
+    /// directory
+    #[arg(long, default_value_t = false)]
+    pub timings: bool,
+
+    /// Compile with clang's `-ftime-trace` and report the most expensive includes, template
+    /// instantiations and functions of each target
+    #[arg(long, default_value_t = false)]
+    pub time_trace: bool,
+
+    /// Build a second time from scratch and check that every artifact is identical
+    #[arg(long, default_value_t = false)]
+    pub verify_reproducible: bool,
+
+    /// Whether this is the second build of `--verify-reproducible`
+    #[arg(skip)]
+    pub reproducibility_check: bool,
+
+    /// Phase of the `jfb pgo` workflow being built, if any
+    #[arg(skip)]
+    pub pgo: Option<PgoPhase>,
+
+    /// Whether this builds the host tools, which never use the active toolchain
+    #[arg(skip)]
+    pub host: bool,
+}
+
+/// Same defaults as the command line, for commands that build on behalf of the user
+impl Default for BuildOpts {
+    fn default() -> Self {
+        Self {
+            profile: "debug".to_string(),
+            toolchain: None,
+            sanitize: vec![],
+            cache: None,
+            explain: false,
+            timings: false,
+            time_trace: false,
+            verify_reproducible: false,
+            reproducibility_check: false,
+            pgo: None,
+            host: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,9 +115,27 @@ pub struct CompileCommand {
     pub file: String,
 }
 
+/// A source file to compile, along with what decides whether it's up to date
+#[derive(Debug, Clone)]
+pub struct CompileStep {
+    pub src: PathBuf,
+    pub obj: PathBuf,
+    pub command: Vec<String>,
+
+    /// Files the object depends on that its depfile doesn't list, like precompiled headers and
+    /// the interfaces of imported modules
+    pub inputs: Vec<PathBuf>,
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FileUpdateCache {
-    last_build_profile: String,
+    /// Command line last used to produce each output file
+    #[serde(default)]
+    commands: HashMap<PathBuf, Vec<String>>,
+
+    /// Identity of the compiler last used to produce each object file
+    #[serde(default)]
+    compilers: HashMap<PathBuf, String>,
 
     #[serde(flatten)]
     cache: HashMap<PathBuf, SystemTime>,
@@ -59,31 +165,37 @@ impl FileUpdateCache {
 pub struct Builder<'a> {
     config: &'a Config,
     opts: &'a BuildOpts,
+    profile: &'a BuildConfig,
     sh: Shell,
     base_dir: PathBuf,
     compile_commands: Mutex<HashMap<PathBuf, CompileCommand>>,
     file_cache: Mutex<FileUpdateCache>,
     config_updated: bool,
+    timings: Timings,
+    probes: Mutex<ProbeCache>,
+    check_results: Mutex<CheckResults>,
 }
 
 impl<'a> Builder<'a> {
-    fn new(args: &Args, config: &'a Config, opts: &'a BuildOpts, base_dir: &Path) -> Result<Self> {
+    pub fn new(
+        args: &Args,
+        config: &'a Config,
+        opts: &'a BuildOpts,
+        base_dir: &Path,
+    ) -> Result<Self> {
         let sh = Shell::new()?;
         let base_dir = base_dir.canonicalize()?;
 
-        // load or initialize our file update cache
-        let cache_path = base_dir
-            .join(&config.workspace.build_dir)
-            .join("jfb_cache.json");
-        let mut file_cache = if std::fs::exists(&cache_path)? {
-            let data = std::fs::read_to_string(cache_path)?;
-            serde_json::from_str(&data)?
-        } else {
-            FileUpdateCache::new()
-        };
+        let profile = validate_opts(config, opts)?;
 
-        // check if the config file has been updated
-        let config_updated = file_cache.is_updated(&args.opts.config)?;
+        // load previously probed compilers, re-probing from scratch if the cache is unreadable
+        let probe_path = base_dir
+            .join(&config.workspace.build_dir)
+            .join("jfb_toolchain.json");
+        let probes = std::fs::read_to_string(&probe_path)
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default();
 
         // load existing compile_commands if available
         let compile_commands_path = base_dir
@@ -104,26 +216,168 @@ impl<'a> Builder<'a> {
             compile_commands.insert(path, compile_command);
         }
 
-        Ok(Self {
+        let mut builder = Self {
             config,
             opts,
+            profile,
             sh,
             base_dir,
             compile_commands: Mutex::new(compile_commands),
-            file_cache: Mutex::new(file_cache),
-            config_updated,
-        })
+            file_cache: Mutex::new(FileUpdateCache::new()),
+            config_updated: false,
+            timings: Timings::new(),
+            probes: Mutex::new(probes),
+            check_results: Mutex::new(CheckResults::default()),
+        };
+
+        // load or initialize our file update cache. each profile and toolchain has its own, so
+        // switching between them only rebuilds what actually changed in each.
+        let cache_path = builder.profile_dir().join("jfb_cache.json");
+        let mut file_cache = if std::fs::exists(&cache_path)? {
+            let data = std::fs::read_to_string(cache_path)?;
+            serde_json::from_str(&data)?
+        } else {
+            FileUpdateCache::new()
+        };
+
+        // check if the config file has been updated
+        builder.config_updated = file_cache.is_updated(&args.opts.config)?;
+        builder.file_cache = Mutex::new(file_cache);
+        builder.init_source_date_epoch();
+
+        // load the results of earlier configuration checks, rerunning them if they're unreadable
+        let check_results = std::fs::read_to_string(builder.check_results_path())
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default();
+        builder.check_results = Mutex::new(check_results);
+
+        Ok(builder)
     }
 
     pub fn build_profile(&self) -> &BuildConfig {
-        &self.config.build_profiles[&self.opts.profile]
+        self.profile
     }
 
-    pub fn should_recompile(&self, path: &Path) -> Result<bool> {
+    /// Name of the active toolchain, selected by `--toolchain` or the build profile
+    pub fn toolchain_name(&self) -> Option<&str> {
+        if self.opts.host {
+            return None;
+        }
+        self.opts
+            .toolchain
+            .as_deref()
+            .or(self.build_profile().toolchain.as_deref())
+    }
+
+    /// The active toolchain, if any
+    pub fn toolchain(&self) -> Option<&ToolchainConfig> {
+        self.toolchain_name()
+            .map(|name| &self.config.toolchains[name])
+    }
+
+    /// Program every compile command is launched through, if any
+    pub fn compiler_launcher(&self) -> Option<&str> {
+        self.opts
+            .cache
+            .as_deref()
+            .or(self.build_profile().compiler_launcher.as_deref())
+    }
+
+    /// Determine why `obj` needs to be recompiled from `src` using `command`, or `None` if it's
+    /// up to date. `inputs` are files the object depends on that the compiler doesn't report in
+    /// its depfile, like a precompiled header.
+    pub fn object_rebuild_reason(
+        &self,
+        src: &Path,
+        obj: &Path,
+        command: &[String],
+        compiler_id: &str,
+        inputs: &[PathBuf],
+    ) -> Result<Option<RebuildReason>> {
         let mut file_cache = self.file_cache.lock().unwrap();
-        Ok(self.config_updated
-            || file_cache.last_build_profile != self.opts.profile
-            || file_cache.is_updated(path)?)
+
+        // always check the source so its modified time gets recorded, whatever the reason
+        let source_newer = file_cache.is_updated(src)?;
+
+        let Some(obj_modified) = modified_time(obj)? else {
+            return Ok(Some(RebuildReason::OutputMissing));
+        };
+        if self.config_updated {
+            return Ok(Some(RebuildReason::ConfigChanged));
+        }
+        if file_cache.commands.get(obj).map(Vec::as_slice) != Some(command) {
+            return Ok(Some(RebuildReason::FlagsChanged));
+        }
+        if file_cache.compilers.get(obj).map(String::as_str) != Some(compiler_id) {
+            return Ok(Some(RebuildReason::CompilerChanged));
+        }
+        if source_newer {
+            return Ok(Some(RebuildReason::SourceNewer));
+        }
+
+        // check the headers the compiler reported on the last build
+        let mut deps = inputs.to_vec();
+        if let Ok(depfile) = std::fs::read_to_string(obj.with_extension("d")) {
+            deps.extend(parse_depfile(&depfile));
+        }
+        for dep in deps {
+            match modified_time(&dep)? {
+                Some(modified) if modified <= obj_modified => {}
+                _ => return Ok(Some(RebuildReason::HeaderChanged(dep))),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Determine why `output` needs to be relinked from `inputs` using `command`, or `None` if
+    /// it's up to date. `rebuilt` lists the inputs that are being rebuilt during this build.
+    pub fn link_rebuild_reason(
+        &self,
+        output: &Path,
+        command: &[String],
+        inputs: &[PathBuf],
+        rebuilt: &[PathBuf],
+    ) -> Result<Option<RebuildReason>> {
+        let file_cache = self.file_cache.lock().unwrap();
+
+        let Some(output_modified) = modified_time(output)? else {
+            return Ok(Some(RebuildReason::OutputMissing));
+        };
+        if self.config_updated {
+            return Ok(Some(RebuildReason::ConfigChanged));
+        }
+        if file_cache.commands.get(output).map(Vec::as_slice) != Some(command) {
+            return Ok(Some(RebuildReason::FlagsChanged));
+        }
+        if let Some(input) = rebuilt.first() {
+            return Ok(Some(RebuildReason::InputChanged(input.clone())));
+        }
+        for input in inputs {
+            match modified_time(input)? {
+                Some(modified) if modified <= output_modified => {}
+                _ => return Ok(Some(RebuildReason::InputChanged(input.clone()))),
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn explain(&self, path: &Path, reason: &RebuildReason) {
+        if self.opts.explain {
+            log::info!("Rebuilding {}: {}", path.display(), reason);
+        } else {
+            log::debug!("Rebuilding {}: {}", path.display(), reason);
+        }
+    }
+
+    fn record_command(&self, output: &Path, command: &[String]) {
+        self.file_cache
+            .lock()
+            .unwrap()
+            .commands
+            .insert(output.to_path_buf(), command.to_vec());
     }
 
     pub fn build(self) -> Result<()> {
@@ -131,145 +385,543 @@ impl<'a> Builder<'a> {
         self.sh.create_dir(&build_dir)?;
         log::debug!("Using build directory: {}", build_dir.display());
 
-        // fetch and build dependencies first
-        self.fetch_dependencies()?;
-        self.build_dependencies()?;
+        // host tools are built by a builder of their own
+        let targets = self
+            .config
+            .targets
+            .iter()
+            .filter(|target| target.host_tool == self.opts.host)
+            .collect::<Vec<_>>();
+
+        // fetch and build dependencies first, unless only host tools that don't use any are built
+        if !self.opts.host || targets.iter().any(|target| !target.dependencies.is_empty()) {
+            self.fetch_dependencies()?;
+            self.build_dependencies()?;
+        }
+        self.run_checks()?;
 
         // compile every target
-        for target in &self.config.targets {
+        for target in targets {
             log::info!("Building target: {}", target.name);
-            self.compile_target(target, &build_dir)?;
+            self.compile_target(target)?;
         }
 
         self.write_build_artifacts()?;
 
         log::info!("All targets built successfully.");
 
+        if self.opts.timings {
+            let report = self.timings.report();
+            report.log_summary();
+            // the host tools are built separately first, so their report goes next to them
+            // instead of being overwritten
+            if self.opts.host {
+                report.write(&self.profile_dir())?;
+            } else {
+                report.write(&build_dir)?;
+            }
+        }
+
         Ok(())
     }
 
-    fn compile_target(&self, target: &TargetConfig, build_dir: &Path) -> Result<()> {
-        // create output directory for this target
-        let out_dir = build_dir.join(&self.opts.profile).join(&target.name);
-        self.sh.create_dir(&out_dir)?;
+    /// Directory holding the outputs of every target under the current profile and toolchain
+    pub fn profile_dir(&self) -> PathBuf {
+        self.profile_dir_for(self.opts.host)
+    }
 
-        let mut src_files = vec![];
-        let mut obj_files = vec![];
+    /// Directory holding the outputs of either the host tools or the other targets
+    fn profile_dir_for(&self, host: bool) -> PathBuf {
+        self.profile_dir_with(host, false)
+    }
+
+    /// Directory holding the outputs of either the host tools or the other targets, optionally
+    /// the one the regular build uses while checking reproducibility
+    pub fn profile_dir_with(&self, host: bool, unverified: bool) -> PathBuf {
+        let build_dir = self.base_dir.join(&self.config.workspace.build_dir);
+
+        // host tools are built without the sanitizers, PGO and reproducibility check asked
+        // for on the command line (see `build_targets`), so their directory never has the
+        // suffixes below
+        let mut profile = self.opts.profile.clone();
+        if !host {
+            // sanitizers requested on the command line get their own directory, so they don't
+            // clobber the regular objects of the profile
+            if !self.opts.sanitize.is_empty() {
+                profile.push_str(&format!("-san-{}", self.sanitizers().join("-")));
+            }
+
+            // the instrumented and optimized builds of `jfb pgo` are kept apart from each other
+            // and from regular builds
+            match self.opts.pgo {
+                Some(PgoPhase::Generate) => profile.push_str("-pgo-instrumented"),
+                Some(PgoPhase::Use) => profile.push_str("-pgo"),
+                None => {}
+            }
+            if self.opts.reproducibility_check && !unverified {
+                profile.push_str("-verify");
+            }
+        }
+
+        // host tools are kept apart, so they don't share a file cache with a native build
+        match self.toolchain_name() {
+            _ if host => build_dir.join("host").join(profile),
+            Some(toolchain) => build_dir.join(toolchain).join(profile),
+            None => build_dir.join(profile),
+        }
+    }
+
+    /// Output directory for the given target under the current profile and toolchain
+    pub fn target_out_dir(&self, target: &TargetConfig) -> PathBuf {
+        self.profile_dir_for(target.host_tool).join(&target.name)
+    }
+
+    /// Directory for the intermediate files jfb generates for a target, kept out of its output
+    /// directory so they can't collide with the target's own outputs
+    pub fn target_internal_dir(&self, target: &TargetConfig) -> PathBuf {
+        self.profile_dir_for(target.host_tool)
+            .join(".jfb")
+            .join(&target.name)
+    }
+
+    /// Find the source files of a target along with the object files they compile to.
+    /// Under a unity build these are the generated combined sources.
+    fn target_sources(&self, target: &TargetConfig) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+        if target.unity_build {
+            let units = self.unity_units(target)?;
+            return Ok(units
+                .into_iter()
+                .map(|unit| (unit.source, unit.object))
+                .unzip());
+        }
+        self.discovered_sources(target)
+    }
 
-        // populate src_files and obj_files
+    /// The compile steps of a target in the order they have to run. Module interfaces come
+    /// before the sources importing them, otherwise sources keep their discovery order.
+    fn compile_steps(
+        &self,
+        target: &TargetConfig,
+        src_files: &[PathBuf],
+        obj_files: &[PathBuf],
+    ) -> Result<Vec<CompileStep>> {
+        let pch_inputs = self.precompiled_header_inputs(target);
+        let modules = self.module_graph(target, src_files, obj_files)?;
+        let order = match &modules {
+            Some(graph) => graph.order.clone(),
+            None => (0..src_files.len()).collect(),
+        };
+
+        Ok(order
+            .into_iter()
+            .map(|index| {
+                let (src, obj) = (&src_files[index], &obj_files[index]);
+                let mut command = self.compile_command(src, obj, target);
+                let mut inputs = pch_inputs.clone();
+                if let Some(graph) = &modules {
+                    command.splice(1..1, self.module_flags(target, graph, index, src));
+                    inputs.extend(graph.required_bmis(index));
+                }
+                CompileStep {
+                    src: src.clone(),
+                    obj: obj.clone(),
+                    command,
+                    inputs,
+                }
+            })
+            .collect())
+    }
+
+    /// Find the source files in the source directories of a target along with the object files
+    /// they compile to
+    fn discovered_sources(&self, target: &TargetConfig) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+        let out_dir = self.target_out_dir(target);
+        let is_source = |path: &Path| {
+            let Some(ext) = path.extension() else {
+                return false;
+            };
+            match target.language {
+                TargetLanguage::C => ext == "c",
+                TargetLanguage::Cpp => {
+                    ext == "cpp" || ext == "cc" || ext == "cxx" || modules::is_module_source(path)
+                }
+            }
+        };
+
+        // populate src_files
+        let mut src_files = vec![];
         for src_dir in target.source_dirs.iter() {
             let src_dir = self.base_dir.join(src_dir);
 
             let entries = self.sh.read_dir(src_dir)?;
             for entry in entries {
-                if entry.is_file()
-                    && let Some(ext) = entry.extension()
-                {
-                    match target.language {
-                        TargetLanguage::C if ext == "c" => {
-                            src_files.push(entry.clone());
-                            let obj_file =
-                                out_dir.join(entry.with_extension("o").file_name().unwrap());
-                            obj_files.push(obj_file);
-                        }
-                        TargetLanguage::Cpp if ext == "cpp" || ext == "cc" || ext == "cxx" => {
-                            src_files.push(entry.clone());
-                            let obj_file =
-                                out_dir.join(entry.with_extension("o").file_name().unwrap());
-                            obj_files.push(obj_file);
+                if entry.is_file() && is_source(&entry) {
+                    src_files.push(entry);
+                }
+            }
+        }
+
+        // generated sources are compiled along with the ones in the source directories
+        src_files.extend(
+            self.generated_files(target)
+                .into_iter()
+                .filter(|file| is_source(file)),
+        );
+        src_files.extend(self.resource_files(target).map(|files| files.source));
+
+        // objects keep the source's extension, so an interface `foo.cppm` and its implementation
+        // `foo.cpp` don't compile to the same object
+        let obj_files: Vec<PathBuf> = src_files
+            .iter()
+            .map(|src| {
+                let mut name = src.file_name().unwrap().to_os_string();
+                name.push(".o");
+                out_dir.join(name)
+            })
+            .collect();
+
+        let mut objects = HashMap::new();
+        for (src, obj) in src_files.iter().zip(obj_files.iter()) {
+            if let Some(other) = objects.insert(obj, src) {
+                return Err(anyhow::anyhow!(
+                    "Sources {} and {} of target `{}` would both compile to {}",
+                    other.display(),
+                    src.display(),
+                    target.name,
+                    obj.display()
+                ));
+            }
+        }
+
+        Ok((src_files, obj_files))
+    }
+
+    fn compile_target(&self, target: &TargetConfig) -> Result<()> {
+        // create output directory for this target
+        let out_dir = self.target_out_dir(target);
+        self.sh.create_dir(&out_dir)?;
+
+        // generated sources have to exist before they can be found and compiled
+        self.run_generators(target)?;
+        self.write_resources(target)?;
+        let (src_files, obj_files) = self.target_sources(target)?;
+
+        // make sure the compiler exists and understands our flags before compiling anything
+        let compiler = self.compiler_info(target)?;
+        self.check_compiler(target, &compiler)?;
+        let compiler_id = compiler.id();
+        log::debug!("Compiling `{}` with {}", target.name, compiler_id);
+        self.write_build_info(target)?;
+
+        // generated sources of a unity build, with the original sources each one combines
+        let unity_members = self.write_unity_sources(target)?;
+
+        // the precompiled header has to be ready before anything that uses it
+        self.build_precompiled_header(target, &compiler_id)?;
+
+        // compile our source files
+        let mut rebuilt = vec![];
+        for step in self.compile_steps(target, &src_files, &obj_files)? {
+            let CompileStep {
+                src,
+                obj,
+                command,
+                inputs,
+            } = &step;
+
+            // check if the file has been updated compared to our last build
+            match self.object_rebuild_reason(src, obj, command, &compiler_id, inputs)? {
+                Some(reason) => {
+                    self.explain(obj, &reason);
+
+                    // tooling should see the original sources rather than the unity sources
+                    match unity_members.get(src) {
+                        Some(members) => {
+                            for member in members {
+                                let command = self.compile_command(member, obj, target);
+                                self.record_compile_command(member, &command);
+                            }
                         }
-                        _ => {}
+                        None => self.record_compile_command(src, command),
                     }
+
+                    self.timings
+                        .time(StepKind::Compile, &target.name, src.display(), || {
+                            self.compile_file(src, obj, command, &compiler_id)
+                        })?;
+                    self.file_cache
+                        .lock()
+                        .unwrap()
+                        .compilers
+                        .insert(obj.clone(), compiler_id.clone());
+                    rebuilt.push(obj.clone());
                 }
+                None => log::debug!("Skipping unchanged file: {}", src.display()),
             }
         }
 
-        // compile our source files
-        for (src, obj) in src_files.iter().zip(obj_files.iter()) {
-            // check if the file has been updated compared to our cached update time
-            if self.should_recompile(src)? {
-                self.compile_file(src, obj, target)?;
-            } else {
-                log::debug!("Skipping unchanged file: {}", src.display());
+        if self.opts.time_trace {
+            self.aggregate_time_traces(target, &obj_files)?;
+        }
+
+        let (output, commands) = self.artifact_commands(target, &obj_files);
+        let command = commands.concat();
+        let mut inputs = obj_files.clone();
+        inputs.extend(self.target_library_files(target));
+
+        match self.link_rebuild_reason(&output, &command, &inputs, &rebuilt)? {
+            Some(reason) => self.explain(&output, &reason),
+            None => {
+                log::debug!("Skipping up-to-date artifact: {}", output.display());
+                return Ok(());
             }
         }
 
+        for step in &commands {
+            let (program, args) = step.split_first().unwrap();
+            self.timings
+                .time(StepKind::Link, &target.name, output.display(), || {
+                    Ok(cmd!(self.sh, "{program}").args(args).quiet().run()?)
+                })?;
+        }
+        self.record_command(&output, &command);
+
         match target.target_type {
-            TargetType::Binary => {
-                // link all object files into the final executable
-                let output_exe = out_dir.join(&target.name);
+            TargetType::Binary | TargetType::Test => {
+                log::debug!("Linked executable: {}", output.display())
+            }
+            TargetType::StaticLibrary => {
+                log::debug!("Created static library: {}", output.display())
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Path of the final artifact of a target under the current profile
+    pub fn target_output(&self, target: &TargetConfig) -> PathBuf {
+        let out_dir = self.target_out_dir(target);
+        match target.target_type {
+            TargetType::Binary | TargetType::Test => out_dir.join(&target.name),
+            TargetType::StaticLibrary => out_dir.join(format!("lib{}.a", &target.name)),
+        }
+    }
 
+    /// Command that runs a built executable, through the toolchain's runner (e.g. qemu-user) when
+    /// cross-compiling
+    pub fn run_command<'s>(&self, sh: &'s Shell, exe: &Path) -> xshell::Cmd<'s> {
+        match self.toolchain().map(|t| t.runner.as_slice()) {
+            Some([runner, runner_args @ ..]) => cmd!(sh, "{runner}").args(runner_args).arg(exe),
+            _ => cmd!(sh, "{exe}"),
+        }
+    }
+
+    /// Commands that produce a target's artifact: the link (or archive) command followed by any
+    /// post-processing like stripping
+    fn artifact_commands(
+        &self,
+        target: &TargetConfig,
+        obj_files: &[PathBuf],
+    ) -> (PathBuf, Vec<Vec<String>>) {
+        let (output, command) = self.link_command(target, obj_files);
+        let mut commands = vec![command];
+        commands.extend(self.post_link_commands(target, &output));
+        (output, commands)
+    }
+
+    /// Build the command line that links (or archives) a target's object files
+    fn link_command(&self, target: &TargetConfig, obj_files: &[PathBuf]) -> (PathBuf, Vec<String>) {
+        let output = self.target_output(target);
+        let mut command = vec![];
+
+        match target.target_type {
+            TargetType::Binary | TargetType::Test => {
+                // link all object files into the final executable
+                let toolchain = self.toolchain();
                 let linker = match target.language {
                     TargetLanguage::C => target
                         .build_overrides
                         .as_ref()
                         .and_then(|overrides| overrides.c_linker.as_ref())
-                        .unwrap_or(&self.build_profile().c_compiler),
+                        .or(toolchain.and_then(|t| t.c_linker.as_ref().or(t.c_compiler.as_ref())))
+                        .unwrap_or(&self.build_profile().c_linker),
                     TargetLanguage::Cpp => target
                         .build_overrides
                         .as_ref()
                         .and_then(|overrides| overrides.cpp_linker.as_ref())
-                        .unwrap_or(&self.build_profile().cpp_compiler),
+                        .or(toolchain
+                            .and_then(|t| t.cpp_linker.as_ref().or(t.cpp_compiler.as_ref())))
+                        .unwrap_or(&self.build_profile().cpp_linker),
                 };
 
                 let library_paths = target
                     .library_dirs
                     .iter()
-                    .map(|dir| format!("-L{}", self.base_dir.join(dir).display()))
-                    .collect::<Vec<_>>();
+                    .map(|dir| format!("-L{}", self.base_dir.join(dir).display()));
 
-                let libraries = target
-                    .libraries
-                    .iter()
-                    .map(|lib| {
-                        let lib_name = lib.file_stem().unwrap().to_string_lossy();
-                        format!("-l{}", lib_name.strip_prefix("lib").unwrap_or(&lib_name))
-                    })
-                    .collect::<Vec<_>>();
-
-                cmd!(self.sh, "{linker}")
-                    .args(&obj_files)
-                    .args(&library_paths)
-                    .args(&libraries)
-                    .arg("-o")
-                    .arg(&output_exe)
-                    .quiet()
-                    .run()?;
-
-                log::debug!("Linked executable: {}", output_exe.display());
+                let libraries = target.libraries.iter().map(|lib| {
+                    let lib_name = lib.file_stem().unwrap().to_string_lossy();
+                    format!("-l{}", lib_name.strip_prefix("lib").unwrap_or(&lib_name))
+                });
+
+                command.push(linker.clone());
+                command.extend(self.toolchain_args(target));
+                command.extend(self.link_flags(target));
+                command.extend(obj_files.iter().map(|obj| obj.display().to_string()));
+                command.extend(library_paths);
+                command.extend(libraries);
+                command.push("-o".to_string());
+                command.push(output.display().to_string());
             }
             TargetType::StaticLibrary => {
                 // archive all object files into a static library
-                let output_lib = out_dir.join(format!("lib{}.a", &target.name));
+                command.push(self.archiver(target));
+                // deterministic mode leaves timestamps, owners and modes out of the archive
+                command.push(if self.reproducible() { "rcsD" } else { "rcs" }.to_string());
+                command.push(output.display().to_string());
+                command.extend(obj_files.iter().map(|obj| obj.display().to_string()));
+            }
+        }
 
-                cmd!(self.sh, "ar")
-                    .arg("rcs")
-                    .arg(&output_lib)
-                    .args(&obj_files)
-                    .quiet()
-                    .run()?;
+        (output, command)
+    }
 
-                log::debug!("Created static library: {}", output_lib.display());
-            }
+    /// Flags passed to the linker from the profile, toolchain, target, sanitizers, coverage, LTO
+    /// and PGO
+    fn link_flags(&self, target: &TargetConfig) -> Vec<String> {
+        self.build_profile()
+            .link_flags
+            .iter()
+            .chain(self.toolchain().iter().flat_map(|t| t.link_flags.iter()))
+            .chain(
+                target
+                    .build_overrides
+                    .as_ref()
+                    .and_then(|overrides| overrides.link_flags.as_ref())
+                    .unwrap_or(&vec![]),
+            )
+            .cloned()
+            .chain(self.sanitizer_flags())
+            .chain(self.coverage_flags())
+            .chain(self.lto_flags(target))
+            .chain(self.pgo_flags(target))
+            .collect()
+    }
+
+    /// Sysroot argument for the active toolchain
+    pub fn sysroot_args(&self) -> Vec<String> {
+        self.toolchain()
+            .and_then(|toolchain| toolchain.sysroot.as_ref())
+            .map(|sysroot| format!("--sysroot={}", self.base_dir.join(sysroot).display()))
+            .into_iter()
+            .collect()
+    }
+
+    /// Target triple argument for the active toolchain. Only Clang takes `--target`, GCC is
+    /// built for a single target and picked by its name instead.
+    pub fn target_triple_args(&self, family: CompilerFamily) -> Vec<String> {
+        match self
+            .toolchain()
+            .and_then(|toolchain| toolchain.target.as_ref())
+        {
+            Some(triple) if family == CompilerFamily::Clang => vec![format!("--target={}", triple)],
+            _ => vec![],
         }
+    }
 
-        Ok(())
+    /// Sysroot and target triple arguments for the active toolchain, shared by compiling and
+    /// linking
+    fn toolchain_args(&self, target: &TargetConfig) -> Vec<String> {
+        let mut args = self.sysroot_args();
+        args.extend(self.target_triple_args(self.compiler_family(target)));
+        args
     }
 
-    fn compile_file(&self, src: &Path, obj: &Path, target: &TargetConfig) -> Result<()> {
-        let compiler = match target.language {
+    /// Find the library files a binary target links against, so changes to them trigger a relink
+    fn target_library_files(&self, target: &TargetConfig) -> Vec<PathBuf> {
+        if target.target_type == TargetType::StaticLibrary {
+            return vec![];
+        }
+
+        let mut files = vec![];
+        for lib in target.libraries.iter() {
+            let lib_name = lib.file_stem().unwrap().to_string_lossy();
+            let lib_name = lib_name.strip_prefix("lib").unwrap_or(&lib_name);
+            for dir in target.library_dirs.iter() {
+                let dir = self.base_dir.join(dir);
+                for candidate in [format!("lib{lib_name}.a"), format!("lib{lib_name}.so")] {
+                    let candidate = dir.join(candidate);
+                    if candidate.is_file() {
+                        files.push(candidate);
+                    }
+                }
+            }
+        }
+        files
+    }
+
+    /// Compiler used for a target, taking target overrides and the active toolchain into account
+    pub fn compiler<'t>(&'t self, target: &'t TargetConfig) -> &'t str {
+        let toolchain = self.toolchain();
+        match target.language {
             TargetLanguage::C => target
                 .build_overrides
                 .as_ref()
                 .and_then(|overrides| overrides.c_compiler.as_ref())
+                .or(toolchain.and_then(|t| t.c_compiler.as_ref()))
                 .unwrap_or(&self.build_profile().c_compiler),
             TargetLanguage::Cpp => target
                 .build_overrides
                 .as_ref()
                 .and_then(|overrides| overrides.cpp_compiler.as_ref())
+                .or(toolchain.and_then(|t| t.cpp_compiler.as_ref()))
                 .unwrap_or(&self.build_profile().cpp_compiler),
-        };
+        }
+    }
+
+    /// Build the full compiler command line (compiler first) for a single source file
+    fn compile_command(&self, src: &Path, obj: &Path, target: &TargetConfig) -> Vec<String> {
+        let mut command = self.compiler_command(target);
+        command.extend(self.precompiled_header_flags(target));
+        command.extend(output_args(src, obj));
+        command
+    }
+
+    /// Compiler, flags, defines and include directories shared by every compile command of a
+    /// target
+    fn compiler_command(&self, target: &TargetConfig) -> Vec<String> {
+        let compiler = self.compiler(target);
 
+        let mut include_dirs = target
+            .include_dirs
+            .iter()
+            .map(|dir| format!("-I{}", self.base_dir.join(dir).display()))
+            .collect::<Vec<_>>();
+        // generated headers, like the build info, are included like any other
+        include_dirs.push(format!("-I{}", self.generated_dir(target).display()));
+        if !self.config.checks.is_empty() {
+            include_dirs.push(format!("-I{}", self.config_header_dir().display()));
+        }
+
+        let mut defines = self
+            .build_profile()
+            .defines
+            .iter()
+            .map(|def| format!("-D{}", def))
+            .collect::<Vec<_>>();
+        if !self.config.checks.is_empty() {
+            defines.push("-DHAVE_CONFIG_H".to_string());
+        }
+
+        let mut command = vec![compiler.to_string()];
+        command.extend(self.compile_flags(target));
+        command.extend(defines);
+        command.extend(include_dirs);
+        command
+    }
+
+    /// Language standard, optimization, warning and other code generation flags for a target
+    fn compile_flags(&self, target: &TargetConfig) -> Vec<String> {
         let standard = match target.language {
             TargetLanguage::C => target
                 .build_overrides
@@ -284,23 +936,11 @@ impl<'a> Builder<'a> {
         };
         let standard_arg = format!("-std={standard}");
 
-        let include_dirs = target
-            .include_dirs
-            .iter()
-            .map(|dir| format!("-I{}", self.base_dir.join(dir).display()))
-            .collect::<Vec<_>>();
-
-        let defines = self
-            .build_profile()
-            .defines
-            .iter()
-            .map(|def| format!("-D{}", def))
-            .collect::<Vec<_>>();
-
         let flags = self
             .build_profile()
             .flags
             .iter()
+            .chain(self.toolchain().iter().flat_map(|t| t.flags.iter()))
             .chain(
                 target
                     .build_overrides
@@ -353,25 +993,30 @@ impl<'a> Builder<'a> {
             extra_args.push("-Werror".to_string());
         }
 
-        let command = cmd!(self.sh, "{compiler}")
-            .arg(&standard_arg)
-            .args(&flags)
-            .args(&defines)
-            .args(&include_dirs)
-            .args(&warnings)
-            .args(&extra_args)
-            .arg(&opt_level)
-            .arg("-c")
-            .arg(src)
-            .arg("-o")
-            .arg(obj);
+        if self.opts.time_trace {
+            extra_args.push("-ftime-trace".to_string());
+        }
 
-        let command_str = command.to_string();
-        let args: Vec<_> = command_str.split_whitespace().map(String::from).collect();
+        let mut args = vec![standard_arg];
+        args.extend(self.toolchain_args(target));
+        args.extend(flags);
+        args.extend(self.check_flags());
+        args.extend(self.sanitizer_flags());
+        args.extend(self.coverage_flags());
+        args.extend(self.lto_flags(target));
+        args.extend(self.pgo_flags(target));
+        args.extend(self.reproducible_flags());
+        args.extend(warnings);
+        args.extend(extra_args);
+        args.push(opt_level);
+        args
+    }
 
+    /// Add the command that compiles `src` to `compile_commands.json`
+    fn record_compile_command(&self, src: &Path, command: &[String]) {
         let compile_command = CompileCommand {
             directory: self.base_dir.to_string_lossy().into_owned(),
-            arguments: args,
+            arguments: command.to_vec(),
             file: src.to_string_lossy().into_owned(),
         };
 
@@ -379,8 +1024,39 @@ impl<'a> Builder<'a> {
             .lock()
             .unwrap()
             .insert(src.to_path_buf(), compile_command);
+    }
 
-        command.quiet().run()?;
+    fn compile_file(
+        &self,
+        src: &Path,
+        obj: &Path,
+        command: &[String],
+        compiler_id: &str,
+    ) -> Result<()> {
+        let cache_key = self.object_cache_key(command, compiler_id)?;
+        if let Some(key) = &cache_key
+            && self.restore_object(key, obj)?
+        {
+            self.record_command(obj, command);
+            log::info!("Restored {} from object cache", obj.display());
+            return Ok(());
+        }
+
+        // the launcher is left out of the recorded command, since it doesn't change the output
+        match self.compiler_launcher() {
+            Some(launcher) => cmd!(self.sh, "{launcher}").args(command).quiet().run()?,
+            None => {
+                let (compiler, args) = command.split_first().unwrap();
+                cmd!(self.sh, "{compiler}").args(args).quiet().run()?
+            }
+        }
+        self.record_command(obj, command);
+
+        if let Some(key) = &cache_key
+            && let Err(e) = self.store_object(key, obj)
+        {
+            log::warn!("Failed to store {} in object cache: {}", obj.display(), e);
+        }
 
         log::info!("Compiled {} to {}", src.display(), obj.display());
 
@@ -389,27 +1065,35 @@ impl<'a> Builder<'a> {
 
     fn write_build_artifacts(&self) -> Result<()> {
         let build_dir = self.base_dir.join(&self.config.workspace.build_dir);
-        let compile_commands_path = build_dir.join("compile_commands.json");
-        let compile_commands_vec: Vec<CompileCommand> = self
-            .compile_commands
-            .lock()
-            .unwrap()
-            .values()
-            .map(ToOwned::to_owned)
-            .collect();
-        let compile_commands_json = serde_json::to_string_pretty(&compile_commands_vec)?;
-        self.sh
-            .write_file(&compile_commands_path, compile_commands_json)?;
-        log::debug!(
-            "Wrote compile commands to {}",
-            compile_commands_path.display()
-        );
 
-        let mut file_cache = self.file_cache.lock().unwrap().clone();
-        file_cache.last_build_profile = self.opts.profile.clone();
+        // the reproducibility check builds elsewhere, so editors keep using the regular build
+        if !self.opts.reproducibility_check {
+            let compile_commands_path = build_dir.join("compile_commands.json");
+            let mut compile_commands_vec: Vec<CompileCommand> = self
+                .compile_commands
+                .lock()
+                .unwrap()
+                .values()
+                .map(ToOwned::to_owned)
+                .collect();
+            compile_commands_vec.sort_by(|a, b| a.file.cmp(&b.file));
+            let compile_commands_json = serde_json::to_string_pretty(&compile_commands_vec)?;
+            self.sh
+                .write_file(&compile_commands_path, compile_commands_json)?;
+            log::debug!(
+                "Wrote compile commands to {}",
+                compile_commands_path.display()
+            );
+        }
+
+        let probes_json = serde_json::to_string_pretty(&*self.probes.lock().unwrap())?;
+        let probe_path = build_dir.join("jfb_toolchain.json");
+        self.sh.write_file(&probe_path, probes_json)?;
+        log::debug!("Wrote compiler probes to {}", probe_path.display());
 
-        let file_update_cache_json = serde_json::to_string_pretty(&file_cache)?;
-        let cache_path = build_dir.join("jfb_cache.json");
+        let file_update_cache_json =
+            serde_json::to_string_pretty(&*self.file_cache.lock().unwrap())?;
+        let cache_path = self.profile_dir().join("jfb_cache.json");
         self.sh.write_file(&cache_path, file_update_cache_json)?;
         log::debug!("Wrote file cache to {}", cache_path.display());
 
@@ -417,6 +1101,19 @@ impl<'a> Builder<'a> {
     }
 }
 
+/// Arguments that compile `src` to `obj`, writing a depfile next to `obj`
+fn output_args(src: &Path, obj: &Path) -> Vec<String> {
+    vec![
+        "-MMD".to_string(),
+        "-MF".to_string(),
+        obj.with_extension("d").display().to_string(),
+        "-c".to_string(),
+        src.display().to_string(),
+        "-o".to_string(),
+        obj.display().to_string(),
+    ]
+}
+
 pub fn build(args: &Args, opts: &BuildOpts) -> Result<()> {
     let cwd = std::env::current_dir()?;
     let base_dir = args
@@ -430,7 +1127,56 @@ pub fn build(args: &Args, opts: &BuildOpts) -> Result<()> {
     let config = Config::load(&args.opts.config)?;
     log::debug!("Loaded config: {:#?}", config);
 
-    Builder::new(args, &config, opts, &base_dir)?.build()?;
+    build_targets(args, &config, opts, &base_dir)?;
+
+    if opts.verify_reproducible {
+        reproducible::verify_reproducible(args, &config, opts, &base_dir)?;
+    }
 
     Ok(())
 }
+
+/// Build the host tools with the host toolchain, then every other target with `opts`
+/// Make sure the selected profile, toolchain and sanitizers exist before we start building
+/// anything, returning the profile
+fn validate_opts<'c>(config: &'c Config, opts: &BuildOpts) -> Result<&'c BuildConfig> {
+    let profile = config.build_profile(&opts.profile)?;
+    if let Some(toolchain) = opts.toolchain.as_ref().or(profile.toolchain.as_ref()) {
+        config.toolchain(toolchain)?;
+    }
+    if opts.sanitize.is_empty() {
+        sanitize::validate_sanitizers(&profile.sanitizers)?;
+    } else {
+        sanitize::validate_sanitizers(&opts.sanitize)?;
+    }
+    Ok(profile)
+}
+
+pub fn build_targets(
+    args: &Args,
+    config: &Config,
+    opts: &BuildOpts,
+    base_dir: &Path,
+) -> Result<()> {
+    // the host tools are built with different options, so the ones asked for are checked
+    // before spending time on them
+    validate_opts(config, opts)?;
+
+    // host tools run during the build, so they have to be ready first
+    if config.targets.iter().any(|target| target.host_tool) {
+        // instrumentation asked for on the command line is meant for the targets being built,
+        // not for the tools building them
+        let host_opts = BuildOpts {
+            toolchain: None,
+            sanitize: vec![],
+            time_trace: false,
+            reproducibility_check: false,
+            pgo: None,
+            host: true,
+            ..opts.clone()
+        };
+        Builder::new(args, config, &host_opts, base_dir)?.build()?;
+    }
+
+    Builder::new(args, config, opts, base_dir)?.build()
+}
@@ -0,0 +1,113 @@
+use std::{
+    fmt::Write as _,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use xshell::cmd;
+
+use crate::{commands::build::Builder, config::TargetConfig};
+
+/// Quote a string as a C string literal
+fn c_string(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
+
+/// Format a Unix timestamp as an ISO 8601 UTC date and time
+fn utc_date_time(timestamp: u64) -> String {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+
+    // civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+impl<'a> Builder<'a> {
+    /// Path of the generated build info header of a target
+    pub fn build_info_header(&self, target: &TargetConfig) -> PathBuf {
+        self.generated_dir(target).join("jfb_build_info.h")
+    }
+
+    /// Output of a git command run in the project directory, or an empty string outside a
+    /// repository
+    fn git_output(&self, args: &[&str]) -> String {
+        let base_dir = &self.base_dir;
+        cmd!(self.sh, "git -C {base_dir}")
+            .args(args)
+            .quiet()
+            .ignore_stderr()
+            .read()
+            .unwrap_or_default()
+    }
+
+    /// Write the build info header of a target. It's only rewritten when its contents change, so
+    /// the sources including it are only recompiled then. The timestamp is the time of the build
+    /// unless `SOURCE_DATE_EPOCH` is set (as it is under a reproducible profile), so without it
+    /// the header changes on every build.
+    pub fn write_build_info(&self, target: &TargetConfig) -> Result<()> {
+        let compiler = self.compiler_info(target)?;
+        let timestamp = match self.source_date_epoch() {
+            Some(timestamp) => timestamp,
+            None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        };
+
+        let workspace = &self.config.workspace;
+        let defines = [
+            ("JFB_PROJECT_NAME", c_string(&workspace.name)),
+            ("JFB_VERSION", c_string(&workspace.version)),
+            ("JFB_TARGET_NAME", c_string(&target.name)),
+            ("JFB_PROFILE", c_string(&self.opts.profile)),
+            (
+                "JFB_GIT_DESCRIBE",
+                c_string(&self.git_output(&["describe", "--always", "--dirty", "--tags"])),
+            ),
+            (
+                "JFB_GIT_COMMIT",
+                c_string(&self.git_output(&["rev-parse", "HEAD"])),
+            ),
+            (
+                "JFB_COMPILER",
+                c_string(&format!(
+                    "{} {} ({})",
+                    compiler.family, compiler.version, compiler.target
+                )),
+            ),
+            ("JFB_BUILD_TIMESTAMP", format!("{}ULL", timestamp)),
+            ("JFB_BUILD_TIME", c_string(&utc_date_time(timestamp))),
+        ];
+
+        let mut contents = String::from(
+            "/* Generated by jfb, do not edit */\n#ifndef JFB_BUILD_INFO_H\n#define JFB_BUILD_INFO_H\n\n",
+        );
+        for (name, value) in defines {
+            writeln!(contents, "#define {} {}", name, value)?;
+        }
+        contents.push_str("\n#endif\n");
+
+        let header = self.build_info_header(target);
+        if std::fs::read_to_string(&header).ok().as_deref() != Some(&contents) {
+            self.sh.write_file(&header, contents)?;
+            log::debug!("Wrote {}", header.display());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+use std::{collections::BTreeMap, fmt::Write as _, path::PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use xshell::cmd;
+
+use crate::{
+    commands::build::Builder,
+    config::{TargetConfig, TargetLanguage},
+};
+
+/// Results of the configuration checks, cached next to the toolchain's build outputs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CheckResults {
+    /// Hash of the checks and the compiler they ran against, so either changing reruns them
+    key: String,
+
+    headers: BTreeMap<String, bool>,
+    functions: BTreeMap<String, bool>,
+    decls: BTreeMap<String, bool>,
+    sizes: BTreeMap<String, u64>,
+    flags: BTreeMap<String, bool>,
+}
+
+/// Macro name for a checked header, function, symbol or type (e.g. `sys/epoll.h` ->
+/// `SYS_EPOLL_H`, `void *` -> `VOID_P`)
+fn macro_name(name: &str) -> String {
+    name.replace('*', " p ")
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+        .to_uppercase()
+}
+
+/// Target standing in for the checks, so they run with the C compiler of the active toolchain
+fn check_target() -> TargetConfig {
+    TargetConfig {
+        name: "checks".to_string(),
+        language: TargetLanguage::C,
+        ..Default::default()
+    }
+}
+
+impl<'a> Builder<'a> {
+    /// Directory the configuration checks of the active toolchain are kept in
+    fn checks_dir(&self) -> PathBuf {
+        let build_dir = self.base_dir.join(&self.config.workspace.build_dir);
+        match self.toolchain_name() {
+            Some(toolchain) => build_dir.join(toolchain),
+            None => build_dir,
+        }
+    }
+
+    /// Path of the cached check results
+    pub fn check_results_path(&self) -> PathBuf {
+        self.checks_dir().join("jfb_checks.json")
+    }
+
+    /// Directory holding the generated `config.h`, on the include path of every target
+    pub fn config_header_dir(&self) -> PathBuf {
+        self.checks_dir().join("jfb_config")
+    }
+
+    /// Flags from `[checks] flags` that the compiler supports, added to every compile command
+    pub fn check_flags(&self) -> Vec<String> {
+        let results = self.check_results.lock().unwrap();
+        self.config
+            .checks
+            .flags
+            .iter()
+            .filter(|flag| results.flags.get(*flag).copied().unwrap_or(false))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether a test program compiles (and links, if `link` is set) with the check compiler
+    fn check_program(&self, target: &TargetConfig, source: &str, link: bool) -> Result<bool> {
+        let dir = self.checks_dir().join("jfb_checks");
+        let src = dir.join("conftest.c");
+        self.sh.write_file(&src, source)?;
+
+        let compiler = self.compiler(target);
+        let toolchain = self.toolchain();
+        let mut args = self.toolchain_args(target);
+        args.extend(toolchain.iter().flat_map(|t| t.flags.iter().cloned()));
+        if link {
+            args.extend(toolchain.iter().flat_map(|t| t.link_flags.iter().cloned()));
+        } else {
+            args.push("-c".to_string());
+        }
+        let output = if link {
+            dir.join("conftest")
+        } else {
+            dir.join("conftest.o")
+        };
+
+        let result = cmd!(self.sh, "{compiler}")
+            .args(&args)
+            .arg(&src)
+            .arg("-o")
+            .arg(&output)
+            .quiet()
+            .ignore_status()
+            .output()?;
+        Ok(result.status.success())
+    }
+
+    /// Size of a type in bytes, found by compiling rather than running so it works when
+    /// cross-compiling. Returns 0 if the type doesn't exist.
+    fn check_size(&self, target: &TargetConfig, includes: &str, ty: &str) -> Result<u64> {
+        let fits = |size: u64| {
+            let source = format!(
+                "{includes}static char jfb_check[(sizeof({ty}) <= {size}) ? 1 : -1];\nint main(void) {{ return jfb_check[0]; }}\n"
+            );
+            self.check_program(target, &source, false)
+        };
+
+        let exists = format!("{includes}int main(void) {{ return (int)sizeof({ty}); }}\n");
+        if !self.check_program(target, &exists, false)? {
+            return Ok(0);
+        }
+
+        let (mut low, mut high) = (1, 1 << 16);
+        if !fits(high)? {
+            return Err(anyhow::anyhow!(
+                "Size of `{}` is larger than {} bytes",
+                ty,
+                high
+            ));
+        }
+        while low < high {
+            let mid = (low + high) / 2;
+            if fits(mid)? {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        Ok(low)
+    }
+
+    /// Run the configuration checks unless the cached results still apply, then generate
+    /// `config.h` from them
+    pub fn run_checks(&self) -> Result<()> {
+        let checks = &self.config.checks;
+        if checks.is_empty() {
+            return Ok(());
+        }
+
+        let target = check_target();
+        let compiler = self.compiler_info(&target)?;
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_string(checks)?.as_bytes());
+        hasher.update([0]);
+        hasher.update(compiler.id().as_bytes());
+        for arg in self.toolchain_args(&target) {
+            hasher.update([0]);
+            hasher.update(arg.as_bytes());
+        }
+        let key = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        if self.check_results.lock().unwrap().key != key {
+            log::info!("Running configuration checks with {}", compiler.id());
+            let includes = checks
+                .includes
+                .iter()
+                .map(|header| format!("#include <{}>\n", header))
+                .collect::<String>();
+
+            let mut results = CheckResults {
+                key,
+                ..Default::default()
+            };
+            for header in &checks.headers {
+                let source = format!("#include <{header}>\nint main(void) {{ return 0; }}\n");
+                let found = self.check_program(&target, &source, false)?;
+                results.headers.insert(header.clone(), found);
+            }
+            for function in &checks.functions {
+                // declared with a dummy signature like autoconf does, so only linking decides
+                let source =
+                    format!("char {function}(void);\nint main(void) {{ return {function}(); }}\n");
+                let found = self.check_program(&target, &source, true)?;
+                results.functions.insert(function.clone(), found);
+            }
+            for decl in &checks.decls {
+                let source = format!(
+                    "{includes}int main(void) {{\n#ifndef {decl}\n    (void){decl};\n#endif\n    return 0;\n}}\n"
+                );
+                let found = self.check_program(&target, &source, false)?;
+                results.decls.insert(decl.clone(), found);
+            }
+            for ty in &checks.sizes {
+                let size = self.check_size(&target, &includes, ty)?;
+                results.sizes.insert(ty.clone(), size);
+            }
+            for flag in &checks.flags {
+                let supported =
+                    self.flags_supported(&target, &compiler, std::slice::from_ref(flag))?;
+                results.flags.insert(flag.clone(), supported);
+            }
+
+            let results_json = serde_json::to_string_pretty(&results)?;
+            self.sh
+                .write_file(self.check_results_path(), results_json)?;
+            *self.check_results.lock().unwrap() = results;
+        }
+
+        self.write_config_header()
+    }
+
+    /// Write `config.h` from the check results, only touching it when they change
+    fn write_config_header(&self) -> Result<()> {
+        let results = self.check_results.lock().unwrap();
+        let mut contents = String::from(
+            "/* Generated by jfb from the [checks] configuration, do not edit */\n#ifndef JFB_CONFIG_H\n#define JFB_CONFIG_H\n\n",
+        );
+        let mut define = |name: String, found: bool| {
+            if found {
+                writeln!(contents, "#define {} 1", name)
+            } else {
+                writeln!(contents, "/* #undef {} */", name)
+            }
+        };
+        for (header, found) in &results.headers {
+            define(format!("HAVE_{}", macro_name(header)), *found)?;
+        }
+        for (function, found) in &results.functions {
+            define(format!("HAVE_{}", macro_name(function)), *found)?;
+        }
+        for (decl, found) in &results.decls {
+            writeln!(
+                contents,
+                "#define HAVE_DECL_{} {}",
+                macro_name(decl),
+                u8::from(*found)
+            )?;
+        }
+        for (ty, size) in &results.sizes {
+            writeln!(contents, "#define SIZEOF_{} {}", macro_name(ty), size)?;
+        }
+        contents.push_str("\n#endif\n");
+
+        let header = self.config_header_dir().join("config.h");
+        if std::fs::read_to_string(&header).ok().as_deref() != Some(&contents) {
+            self.sh.write_file(&header, contents)?;
+            log::debug!("Wrote {}", header.display());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,599 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use serde::Deserialize;
+use xshell::cmd;
+
+use crate::{
+    commands::build::{
+        Builder,
+        probe::{CompilerFamily, companion_tool},
+    },
+    config::{CoverageMode, TargetType},
+};
+
+/// Output of `gcov --json-format` for a single object file
+#[derive(Debug, Deserialize)]
+struct GcovOutput {
+    current_working_directory: PathBuf,
+    files: Vec<GcovFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GcovFile {
+    file: PathBuf,
+
+    #[serde(default)]
+    lines: Vec<GcovLine>,
+
+    #[serde(default)]
+    functions: Vec<GcovFunction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GcovLine {
+    line_number: u32,
+    count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct GcovFunction {
+    demangled_name: String,
+    start_line: u32,
+    execution_count: u64,
+}
+
+/// Execution counts of a single source file
+#[derive(Debug, Clone, Default)]
+pub struct FileCoverage {
+    /// Execution count of each instrumented line
+    pub lines: BTreeMap<u32, u64>,
+
+    /// Start line and execution count of each function
+    pub functions: BTreeMap<String, (u32, u64)>,
+}
+
+impl FileCoverage {
+    /// Add the counts of another run over the same file (e.g. a header compiled into several
+    /// objects)
+    fn merge(&mut self, other: FileCoverage) {
+        for (line, count) in other.lines {
+            *self.lines.entry(line).or_default() += count;
+        }
+        for (name, (line, count)) in other.functions {
+            self.functions.entry(name).or_insert((line, 0)).1 += count;
+        }
+    }
+
+    fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|count| **count > 0).count()
+    }
+
+    fn functions_hit(&self) -> usize {
+        self.functions
+            .values()
+            .filter(|(_, count)| *count > 0)
+            .count()
+    }
+}
+
+/// Line and function coverage of the project's own sources, keyed by path relative to the
+/// project directory
+#[derive(Debug, Clone, Default)]
+pub struct CoverageReport {
+    pub files: BTreeMap<PathBuf, FileCoverage>,
+}
+
+fn percent(hit: usize, total: usize) -> f64 {
+    if total == 0 {
+        100.0
+    } else {
+        hit as f64 * 100.0 / total as f64
+    }
+}
+
+fn rate(hit: usize, total: usize) -> String {
+    format!("{:.4}", percent(hit, total) / 100.0)
+}
+
+/// Escape text for use in HTML and XML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl CoverageReport {
+    fn add(&mut self, path: PathBuf, coverage: FileCoverage) {
+        self.files.entry(path).or_default().merge(coverage);
+    }
+
+    /// Hit and total counts of lines and functions across all files
+    fn totals(&self) -> (usize, usize, usize, usize) {
+        self.files.values().fold((0, 0, 0, 0), |totals, file| {
+            (
+                totals.0 + file.lines_hit(),
+                totals.1 + file.lines.len(),
+                totals.2 + file.functions_hit(),
+                totals.3 + file.functions.len(),
+            )
+        })
+    }
+
+    pub fn log_summary(&self) {
+        if self.files.is_empty() {
+            log::warn!("No coverage data was collected for the project's sources");
+            return;
+        }
+
+        log::info!("Coverage summary:");
+        log::info!("  {:>7}  {:>9}  File", "Lines", "Functions");
+        for (path, file) in &self.files {
+            log::info!(
+                "  {:>6.1}%  {:>8.1}%  {}",
+                percent(file.lines_hit(), file.lines.len()),
+                percent(file.functions_hit(), file.functions.len()),
+                path.display()
+            );
+        }
+
+        let (lines_hit, lines, functions_hit, functions) = self.totals();
+        log::info!(
+            "Total: {:.1}% of lines ({}/{}), {:.1}% of functions ({}/{})",
+            percent(lines_hit, lines),
+            lines_hit,
+            lines,
+            percent(functions_hit, functions),
+            functions_hit,
+            functions
+        );
+    }
+
+    /// Write the report in lcov, Cobertura and HTML formats to `dir`
+    pub fn write(&self, dir: &Path, base_dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join("coverage.lcov"), self.to_lcov(base_dir))?;
+        std::fs::write(dir.join("cobertura.xml"), self.to_cobertura(base_dir))?;
+        std::fs::write(dir.join("index.html"), self.to_html(base_dir))?;
+        Ok(())
+    }
+
+    fn to_lcov(&self, base_dir: &Path) -> String {
+        let mut out = String::new();
+        for (path, file) in &self.files {
+            let _ = writeln!(out, "TN:");
+            let _ = writeln!(out, "SF:{}", base_dir.join(path).display());
+            for (name, (line, _)) in &file.functions {
+                let _ = writeln!(out, "FN:{},{}", line, name);
+            }
+            for (name, (_, count)) in &file.functions {
+                let _ = writeln!(out, "FNDA:{},{}", count, name);
+            }
+            let _ = writeln!(out, "FNF:{}", file.functions.len());
+            let _ = writeln!(out, "FNH:{}", file.functions_hit());
+            for (line, count) in &file.lines {
+                let _ = writeln!(out, "DA:{},{}", line, count);
+            }
+            let _ = writeln!(out, "LF:{}", file.lines.len());
+            let _ = writeln!(out, "LH:{}", file.lines_hit());
+            let _ = writeln!(out, "end_of_record");
+        }
+        out
+    }
+
+    fn to_cobertura(&self, base_dir: &Path) -> String {
+        let (lines_hit, lines, _, _) = self.totals();
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        let mut out = String::new();
+        let _ = writeln!(out, r#"<?xml version="1.0" ?>"#);
+        let _ = writeln!(
+            out,
+            r#"<!DOCTYPE coverage SYSTEM "http://cobertura.sourceforge.net/xml/coverage-04.dtd">"#
+        );
+        let _ = writeln!(
+            out,
+            r#"<coverage line-rate="{}" branch-rate="0" lines-covered="{}" lines-valid="{}" branches-covered="0" branches-valid="0" complexity="0" version="jfb {}" timestamp="{}">"#,
+            rate(lines_hit, lines),
+            lines_hit,
+            lines,
+            env!("CARGO_PKG_VERSION"),
+            timestamp
+        );
+        let _ = writeln!(
+            out,
+            "  <sources><source>{}</source></sources>",
+            escape(&base_dir.display().to_string())
+        );
+        let _ = writeln!(out, "  <packages>");
+        let _ = writeln!(
+            out,
+            r#"    <package name="." line-rate="{}" branch-rate="0" complexity="0">"#,
+            rate(lines_hit, lines)
+        );
+        let _ = writeln!(out, "      <classes>");
+        for (path, file) in &self.files {
+            let path = escape(&path.display().to_string());
+            let _ = writeln!(
+                out,
+                r#"        <class name="{}" filename="{}" line-rate="{}" branch-rate="0" complexity="0">"#,
+                path,
+                path,
+                rate(file.lines_hit(), file.lines.len())
+            );
+            let _ = writeln!(out, "          <methods/>");
+            let _ = writeln!(out, "          <lines>");
+            for (line, count) in &file.lines {
+                let _ = writeln!(
+                    out,
+                    r#"            <line number="{}" hits="{}"/>"#,
+                    line, count
+                );
+            }
+            let _ = writeln!(out, "          </lines>");
+            let _ = writeln!(out, "        </class>");
+        }
+        let _ = writeln!(out, "      </classes>");
+        let _ = writeln!(out, "    </package>");
+        let _ = writeln!(out, "  </packages>");
+        let _ = writeln!(out, "</coverage>");
+        out
+    }
+
+    fn to_html(&self, base_dir: &Path) -> String {
+        let (lines_hit, lines, functions_hit, functions) = self.totals();
+
+        let mut out = String::new();
+        out.push_str(concat!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+            "<title>Coverage report</title>\n<style>\n",
+            "body { font-family: sans-serif; }\n",
+            "table { border-collapse: collapse; }\n",
+            "td, th { padding: 2px 8px; text-align: left; }\n",
+            "pre { margin: 0; }\n",
+            ".count { text-align: right; color: #666; }\n",
+            ".hit { background: #dfd; }\n",
+            ".miss { background: #fdd; }\n",
+            "</style>\n</head>\n<body>\n",
+        ));
+        let _ = writeln!(out, "<h1>Coverage report</h1>");
+        let _ = writeln!(
+            out,
+            "<p>Lines: {:.1}% ({}/{}), functions: {:.1}% ({}/{})</p>",
+            percent(lines_hit, lines),
+            lines_hit,
+            lines,
+            percent(functions_hit, functions),
+            functions_hit,
+            functions
+        );
+
+        let _ = writeln!(
+            out,
+            "<table>\n<tr><th>File</th><th>Lines</th><th>Functions</th></tr>"
+        );
+        for (index, (path, file)) in self.files.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "<tr><td><a href=\"#file{}\">{}</a></td><td>{:.1}%</td><td>{:.1}%</td></tr>",
+                index,
+                escape(&path.display().to_string()),
+                percent(file.lines_hit(), file.lines.len()),
+                percent(file.functions_hit(), file.functions.len())
+            );
+        }
+        let _ = writeln!(out, "</table>");
+
+        // annotated sources, with lines that never ran highlighted
+        for (index, (path, file)) in self.files.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "<h2 id=\"file{}\">{}</h2>",
+                index,
+                escape(&path.display().to_string())
+            );
+            let Ok(source) = std::fs::read_to_string(base_dir.join(path)) else {
+                let _ = writeln!(out, "<p>Source not available</p>");
+                continue;
+            };
+            let _ = writeln!(out, "<table>");
+            for (number, text) in (1..).zip(source.lines()) {
+                let (class, count) = match file.lines.get(&number) {
+                    Some(0) => ("miss", "0".to_string()),
+                    Some(count) => ("hit", count.to_string()),
+                    None => ("", String::new()),
+                };
+                let _ = writeln!(
+                    out,
+                    "<tr class=\"{}\"><td class=\"count\">{}</td><td class=\"count\">{}</td><td><pre>{}</pre></td></tr>",
+                    class,
+                    number,
+                    count,
+                    escape(text)
+                );
+            }
+            let _ = writeln!(out, "</table>");
+        }
+
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+/// Parse an lcov tracefile into the coverage of each file it mentions
+fn parse_lcov(contents: &str) -> Vec<(PathBuf, FileCoverage)> {
+    let mut files = vec![];
+    let mut current: Option<(PathBuf, FileCoverage)> = None;
+
+    for line in contents.lines() {
+        if line == "end_of_record" {
+            files.extend(current.take());
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        if key == "SF" {
+            current = Some((PathBuf::from(value), FileCoverage::default()));
+            continue;
+        }
+        let Some((_, file)) = current.as_mut() else {
+            continue;
+        };
+
+        let fields = value.split(',').collect::<Vec<_>>();
+        match (key, fields.as_slice()) {
+            // line number, execution count and an optional checksum
+            ("DA", [line, count, ..]) => {
+                if let (Ok(line), Ok(count)) = (line.parse(), count.parse::<u64>()) {
+                    *file.lines.entry(line).or_default() += count;
+                }
+            }
+            ("FN", [line, name @ ..]) => {
+                if let Ok(line) = line.parse() {
+                    file.functions.entry(name.join(",")).or_insert((line, 0));
+                }
+            }
+            ("FNDA", [count, name @ ..]) => {
+                if let Ok(count) = count.parse::<u64>() {
+                    file.functions.entry(name.join(",")).or_insert((0, 0)).1 += count;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    files
+}
+
+impl<'a> Builder<'a> {
+    /// Flags that enable coverage instrumentation, used for both compiling and linking
+    pub fn coverage_flags(&self) -> Vec<String> {
+        match self.build_profile().coverage {
+            CoverageMode::Off => vec![],
+            CoverageMode::Gcov => vec!["--coverage".to_string()],
+            CoverageMode::Source => vec![
+                "-fprofile-instr-generate".to_string(),
+                "-fcoverage-mapping".to_string(),
+            ],
+        }
+    }
+
+    /// Directory the coverage data and reports of the current profile are written to
+    pub fn coverage_dir(&self) -> PathBuf {
+        self.profile_dir().join("jfb-coverage")
+    }
+
+    fn profraw_dir(&self) -> PathBuf {
+        self.coverage_dir().join("profraw")
+    }
+
+    /// Environment variables to run instrumented executables with
+    pub fn coverage_env(&self) -> Vec<(&'static str, String)> {
+        match self.build_profile().coverage {
+            CoverageMode::Source => vec![(
+                "LLVM_PROFILE_FILE",
+                self.profraw_dir()
+                    .join("%p-%m.profraw")
+                    .display()
+                    .to_string(),
+            )],
+            _ => vec![],
+        }
+    }
+
+    /// Check that every target is built by a compiler whose coverage data we can read
+    pub fn check_coverage_support(&self) -> Result<()> {
+        let mode = self.build_profile().coverage;
+        let (family, setting) = match mode {
+            CoverageMode::Off => return Ok(()),
+            CoverageMode::Gcov => (CompilerFamily::Gcc, "gcov"),
+            CoverageMode::Source => (CompilerFamily::Clang, "source"),
+        };
+
+        for target in &self.config.targets {
+            let info = self.compiler_info(target)?;
+            if info.family != family {
+                return Err(anyhow::anyhow!(
+                    "`coverage = \"{}\"` needs {}, but target `{}` is built with {} {}",
+                    setting,
+                    family,
+                    target.name,
+                    info.family,
+                    info.version
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove the counters left behind by previous runs, so the report only covers the next one
+    pub fn reset_coverage(&self) -> Result<()> {
+        match self.build_profile().coverage {
+            CoverageMode::Off => {}
+            CoverageMode::Gcov => {
+                for target in &self.config.targets {
+                    let (_, obj_files) = self.target_sources(target)?;
+                    for counters in obj_files.iter().map(|obj| obj.with_extension("gcda")) {
+                        if counters.exists() {
+                            self.sh.remove_path(counters)?;
+                        }
+                    }
+                }
+            }
+            CoverageMode::Source => self.sh.remove_path(self.profraw_dir())?,
+        }
+        Ok(())
+    }
+
+    /// Collect the coverage of the project's own sources after the tests have run
+    pub fn collect_coverage(&self) -> Result<CoverageReport> {
+        match self.build_profile().coverage {
+            CoverageMode::Off => Ok(CoverageReport::default()),
+            CoverageMode::Gcov => self.collect_gcov_coverage(),
+            CoverageMode::Source => self.collect_source_coverage(),
+        }
+    }
+
+    /// Path of a covered file relative to the project directory, or `None` if it isn't one of
+    /// the project's own sources (dependencies, generated files or system headers)
+    fn project_source(&self, path: &Path) -> Option<PathBuf> {
+        let relative = path.strip_prefix(&self.base_dir).ok()?;
+        let workspace = &self.config.workspace;
+        if relative.starts_with(&workspace.dep_dir) || relative.starts_with(&workspace.build_dir) {
+            return None;
+        }
+        Some(relative.to_path_buf())
+    }
+
+    fn collect_gcov_coverage(&self) -> Result<CoverageReport> {
+        let mut report = CoverageReport::default();
+
+        for target in &self.config.targets {
+            let gcov = companion_tool(self.compiler(target), &["g++", "gcc"], "gcov");
+            let (src_files, obj_files) = self.target_sources(target)?;
+
+            for (src, obj) in src_files.iter().zip(obj_files.iter()) {
+                // objects without notes weren't instrumented
+                if !obj.with_extension("gcno").is_file() {
+                    continue;
+                }
+
+                // objects that never ran have no counters, which gcov reports as zero
+                let output = cmd!(
+                    self.sh,
+                    "{gcov} --json-format --stdout --object-directory {obj} {src}"
+                )
+                .quiet()
+                .ignore_stderr()
+                .read()?;
+                let output: GcovOutput = serde_json::from_str(&output).map_err(|e| {
+                    anyhow::anyhow!("Failed to parse gcov output for {}: {}", src.display(), e)
+                })?;
+
+                for file in output.files {
+                    let path = output.current_working_directory.join(&file.file);
+                    let Some(path) = self.project_source(&path) else {
+                        continue;
+                    };
+                    let coverage = FileCoverage {
+                        lines: file
+                            .lines
+                            .iter()
+                            .map(|line| (line.line_number, line.count))
+                            .collect(),
+                        functions: file
+                            .functions
+                            .into_iter()
+                            .map(|function| {
+                                (
+                                    function.demangled_name,
+                                    (function.start_line, function.execution_count),
+                                )
+                            })
+                            .collect(),
+                    };
+                    report.add(path, coverage);
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn collect_source_coverage(&self) -> Result<CoverageReport> {
+        let mut report = CoverageReport::default();
+
+        let tests = self
+            .config
+            .targets
+            .iter()
+            .filter(|target| target.target_type == TargetType::Test)
+            .collect::<Vec<_>>();
+        let Some(first) = tests.first() else {
+            return Ok(report);
+        };
+
+        let profraw_dir = self.profraw_dir();
+        let profiles = if profraw_dir.is_dir() {
+            self.sh
+                .read_dir(&profraw_dir)?
+                .into_iter()
+                .filter(|path| path.extension().is_some_and(|ext| ext == "profraw"))
+                .collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+        if profiles.is_empty() {
+            log::warn!("The tests didn't write any coverage data");
+            return Ok(report);
+        }
+
+        let compiler = self.compiler(first);
+        let profdata_tool = companion_tool(compiler, &["clang++", "clang"], "llvm-profdata");
+        let cov_tool = companion_tool(compiler, &["clang++", "clang"], "llvm-cov");
+
+        let profdata = self.coverage_dir().join("coverage.profdata");
+        cmd!(
+            self.sh,
+            "{profdata_tool} merge -sparse {profiles...} -o {profdata}"
+        )
+        .quiet()
+        .run()?;
+
+        // every test executable after the first is passed as an extra object
+        let executables = tests
+            .iter()
+            .map(|test| self.target_output(test))
+            .collect::<Vec<_>>();
+        let (executable, others) = executables.split_first().unwrap();
+        let objects = others
+            .iter()
+            .flat_map(|exe| ["-object".into(), exe.clone().into_os_string()]);
+        let lcov = cmd!(
+            self.sh,
+            "{cov_tool} export -format=lcov -instr-profile {profdata} {executable}"
+        )
+        .args(objects)
+        .quiet()
+        .read()?;
+
+        for (path, coverage) in parse_lcov(&lcov) {
+            if let Some(path) = self.project_source(&self.base_dir.join(path)) {
+                report.add(path, coverage);
+            }
+        }
+
+        Ok(report)
+    }
+}
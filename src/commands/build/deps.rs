@@ -1,7 +1,10 @@
 use anyhow::Result;
 use xshell::cmd;
 
-use crate::{commands::build::Builder, config::DependencyConfig};
+use crate::{
+    commands::build::{Builder, probe::CompilerFamily, timings::StepKind},
+    config::{DependencyConfig, LtoMode, TargetConfig, TargetLanguage},
+};
 
 impl<'a> Builder<'a> {
     pub fn download_dependency(&self, dep_name: &str, dep: &DependencyConfig) -> Result<()> {
@@ -48,7 +51,11 @@ impl<'a> Builder<'a> {
             ));
         }
 
-        let build_path = target_path.join("build");
+        // keep each toolchain's build of the dependency separate
+        let build_path = match self.toolchain_name() {
+            Some(toolchain) => target_path.join(format!("build-{}", toolchain)),
+            None => target_path.join("build"),
+        };
         if !build_path.exists() {
             self.sh.create_dir(&build_path)?;
         }
@@ -62,14 +69,76 @@ impl<'a> Builder<'a> {
         for flag in &self.build_profile().cmake_flags {
             cmake_cmd = cmake_cmd.arg(flag);
         }
-        cmake_cmd.quiet().run()?;
+        for flag in self.toolchain_cmake_flags() {
+            cmake_cmd = cmake_cmd.arg(flag);
+        }
+        if self.build_profile().lto != LtoMode::Off {
+            cmake_cmd = cmake_cmd.arg("-DCMAKE_INTERPROCEDURAL_OPTIMIZATION=ON");
+        }
+        if let Some(launcher) = self.compiler_launcher() {
+            cmake_cmd = cmake_cmd
+                .arg(format!("-DCMAKE_C_COMPILER_LAUNCHER={}", launcher))
+                .arg(format!("-DCMAKE_CXX_COMPILER_LAUNCHER={}", launcher));
+        }
+        self.timings
+            .time(StepKind::Configure, dep_name, dep_name, || {
+                Ok(cmake_cmd.quiet().run()?)
+            })?;
 
         log::info!("Building dependency `{}`", dep_name);
-        cmd!(self.sh, "cmake --build .").quiet().run()?;
+        self.timings
+            .time(StepKind::Dependency, dep_name, dep_name, || {
+                Ok(cmd!(self.sh, "cmake --build .").quiet().run()?)
+            })?;
 
         Ok(())
     }
 
+    /// CMake flags that make a dependency build with the active toolchain
+    pub fn toolchain_cmake_flags(&self) -> Vec<String> {
+        let Some(toolchain) = self.toolchain() else {
+            return vec![];
+        };
+
+        // a toolchain file describes everything on its own
+        if let Some(file) = &toolchain.cmake_toolchain_file {
+            return vec![format!(
+                "-DCMAKE_TOOLCHAIN_FILE={}",
+                self.base_dir.join(file).display()
+            )];
+        }
+
+        let mut flags = vec![];
+        if let Some(compiler) = &toolchain.c_compiler {
+            flags.push(format!("-DCMAKE_C_COMPILER={}", compiler));
+        }
+        if let Some(compiler) = &toolchain.cpp_compiler {
+            flags.push(format!("-DCMAKE_CXX_COMPILER={}", compiler));
+        }
+        if let Some(archiver) = &toolchain.archiver {
+            flags.push(format!("-DCMAKE_AR={}", archiver));
+        }
+        if let Some(sysroot) = &toolchain.sysroot {
+            flags.push(format!(
+                "-DCMAKE_SYSROOT={}",
+                self.base_dir.join(sysroot).display()
+            ));
+        }
+        // like `--target`, only Clang takes the triple
+        if let Some(triple) = &toolchain.target {
+            for (language, name) in [(TargetLanguage::C, "C"), (TargetLanguage::Cpp, "CXX")] {
+                let target = TargetConfig {
+                    language,
+                    ..Default::default()
+                };
+                if self.compiler_family(&target) == CompilerFamily::Clang {
+                    flags.push(format!("-DCMAKE_{}_COMPILER_TARGET={}", name, triple));
+                }
+            }
+        }
+        flags
+    }
+
     pub fn build_dependencies(&self) -> Result<()> {
         for (dep_name, dep) in self.config.dependencies.iter() {
             self.build_dependency(dep_name, dep)?;
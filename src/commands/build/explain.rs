@@ -0,0 +1,192 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+
+use crate::commands::build::{Builder, CompileStep};
+
+/// Reason an object file or linked artifact needs to be rebuilt
+#[derive(Debug, Clone, PartialEq)]
+pub enum RebuildReason {
+    /// The output file does not exist yet
+    OutputMissing,
+
+    /// The configuration file changed since the last build
+    ConfigChanged,
+
+    /// The command used to produce the output changed since the last build
+    FlagsChanged,
+
+    /// The object file was built by a different compiler or compiler version
+    CompilerChanged,
+
+    /// The source file is newer than its last recorded build
+    SourceNewer,
+
+    /// A header included by the source file is newer than the object file
+    HeaderChanged(PathBuf),
+
+    /// An input to the link step is being rebuilt or is newer than the output
+    InputChanged(PathBuf),
+}
+
+impl fmt::Display for RebuildReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RebuildReason::OutputMissing => write!(f, "output missing"),
+            RebuildReason::ConfigChanged => write!(f, "config changed"),
+            RebuildReason::FlagsChanged => write!(f, "flags changed"),
+            RebuildReason::CompilerChanged => write!(f, "compiler changed"),
+            RebuildReason::SourceNewer => write!(f, "source newer"),
+            RebuildReason::HeaderChanged(path) => {
+                write!(f, "header {} changed", path.display())
+            }
+            RebuildReason::InputChanged(path) => write!(f, "input {} changed", path.display()),
+        }
+    }
+}
+
+/// Modification time of the file at `path`, or `None` if it doesn't exist
+pub fn modified_time(path: &Path) -> Result<Option<SystemTime>> {
+    match std::fs::metadata(path) {
+        Ok(metadata) => Ok(Some(metadata.modified()?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parse the prerequisites out of a Makefile-style dependency file as emitted by `-MMD`
+pub fn parse_depfile(contents: &str) -> Vec<PathBuf> {
+    // join line continuations, then skip past the `target:` part of the first rule. Later rules
+    // (like the ones GCC adds for modules) don't describe the object's own prerequisites.
+    let joined = contents.replace("\\\r\n", " ").replace("\\\n", " ");
+    let first_rule = joined.lines().next().unwrap_or_default();
+    let Some((_, prereqs)) = first_rule.split_once(": ") else {
+        return vec![];
+    };
+
+    let mut deps = vec![];
+    let mut current = String::new();
+    let mut chars = prereqs.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => {
+                current.push(' ');
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    deps.push(PathBuf::from(std::mem::take(&mut current)));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        deps.push(PathBuf::from(current));
+    }
+
+    deps
+}
+
+impl<'a> Builder<'a> {
+    /// Report whether `file` (a source file, object file or target artifact) would be rebuilt,
+    /// and why
+    pub fn why(&self, file: &Path) -> Result<()> {
+        for target in &self.config.targets {
+            let (src_files, obj_files) = self.target_sources(target)?;
+
+            let compiler_id = self.compiler_info(target)?.id();
+
+            if let Some(pch) = self.precompiled_header(target)
+                && (pch.output == file || pch.header == file)
+            {
+                let command = self.precompiled_header_command(target, &pch);
+                let reason = self.object_rebuild_reason(
+                    &pch.wrapper,
+                    &pch.output,
+                    &command,
+                    &compiler_id,
+                    &[],
+                )?;
+                report(&pch.output, reason.as_ref());
+                return Ok(());
+            }
+            for rule in &target.generate {
+                let generated_dir = self.generated_dir(target);
+                if rule
+                    .outputs
+                    .iter()
+                    .any(|output| generated_dir.join(output) == file)
+                {
+                    let reason = self.generate_rebuild_reason(target, rule)?;
+                    report(file, reason.as_ref().map(|(_, reason)| reason));
+                    return Ok(());
+                }
+            }
+
+            if let Some(files) = self.resource_files(target)
+                && (files.source == file || files.header == file)
+            {
+                let reason = self.resources_rebuild_reason(target)?;
+                report(file, reason.as_ref());
+                return Ok(());
+            }
+
+            let unity_units = if target.unity_build {
+                self.unity_units(target)?
+            } else {
+                vec![]
+            };
+
+            let mut rebuilt = vec![];
+            for step in self.compile_steps(target, &src_files, &obj_files)? {
+                let CompileStep {
+                    src,
+                    obj,
+                    command,
+                    inputs,
+                } = &step;
+                let reason = self.object_rebuild_reason(src, obj, command, &compiler_id, inputs)?;
+
+                // under a unity build, a source is rebuilt as part of its unit
+                let is_member = unity_units
+                    .iter()
+                    .any(|unit| &unit.source == src && unit.members.iter().any(|m| m == file));
+                if src == file || obj == file || is_member {
+                    report(obj, reason.as_ref());
+                    return Ok(());
+                }
+
+                if reason.is_some() {
+                    rebuilt.push(obj.clone());
+                }
+            }
+
+            let (output, commands) = self.artifact_commands(target, &obj_files);
+            if output == file {
+                let mut inputs = obj_files.clone();
+                inputs.extend(self.target_library_files(target));
+                let reason =
+                    self.link_rebuild_reason(&output, &commands.concat(), &inputs, &rebuilt)?;
+                report(&output, reason.as_ref());
+                return Ok(());
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "`{}` is not a source file or build output of any target",
+            file.display()
+        ))
+    }
+}
+
+fn report(path: &Path, reason: Option<&RebuildReason>) {
+    match reason {
+        Some(reason) => log::info!("{} would be rebuilt: {}", path.display(), reason),
+        None => log::info!("{} is up to date", path.display()),
+    }
+}
@@ -0,0 +1,186 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use xshell::cmd;
+
+use crate::{
+    commands::build::{Builder, explain::RebuildReason, timings::StepKind},
+    config::{GenerateRule, TargetConfig},
+};
+
+impl<'a> Builder<'a> {
+    /// Directory the custom build steps of a target write their outputs to
+    pub fn generated_dir(&self, target: &TargetConfig) -> PathBuf {
+        self.target_internal_dir(target).join("gen")
+    }
+
+    /// Every file written by the custom build steps of a target
+    pub fn generated_files(&self, target: &TargetConfig) -> Vec<PathBuf> {
+        let generated_dir = self.generated_dir(target);
+        target
+            .generate
+            .iter()
+            .flat_map(|rule| rule.outputs.iter().map(|output| generated_dir.join(output)))
+            .collect()
+    }
+
+    /// Host tool targets a custom build step runs, by the placeholder that refers to them
+    fn generate_tools<'t>(
+        &'t self,
+        target: &TargetConfig,
+        rule: &GenerateRule,
+    ) -> Result<Vec<(String, &'t TargetConfig)>> {
+        let mut tools = vec![];
+        for arg in &rule.command {
+            let mut rest = arg.as_str();
+            while let Some(start) = rest.find("{tool:") {
+                let Some(end) = rest[start..].find('}') else {
+                    break;
+                };
+                let name = &rest[start + "{tool:".len()..start + end];
+                let tool = self
+                    .config
+                    .targets
+                    .iter()
+                    .find(|t| t.name == name && t.host_tool)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Generate step of target `{}` uses `{{tool:{}}}`, but there's no host tool target named `{}`",
+                            target.name,
+                            name,
+                            name
+                        )
+                    })?;
+                tools.push((format!("{{tool:{}}}", name), tool));
+                rest = &rest[start + end..];
+            }
+        }
+        Ok(tools)
+    }
+
+    /// Command of a custom build step with its placeholders filled in
+    fn generate_command(&self, target: &TargetConfig, rule: &GenerateRule) -> Result<Vec<String>> {
+        let generated_dir = self.generated_dir(target);
+        let tools = self.generate_tools(target, rule)?;
+        let mut command = vec![];
+        for arg in &rule.command {
+            match arg.as_str() {
+                "{inputs}" => command.extend(
+                    rule.inputs
+                        .iter()
+                        .map(|input| self.base_dir.join(input).display().to_string()),
+                ),
+                "{outputs}" => command.extend(
+                    rule.outputs
+                        .iter()
+                        .map(|output| generated_dir.join(output).display().to_string()),
+                ),
+                _ => {
+                    let mut arg = arg.replace("{gen_dir}", &generated_dir.display().to_string());
+                    for (placeholder, tool) in &tools {
+                        arg = arg
+                            .replace(placeholder, &self.target_output(tool).display().to_string());
+                    }
+                    command.push(arg);
+                }
+            }
+        }
+        Ok(command)
+    }
+
+    /// Why the outputs of a custom build step need to be regenerated, or `None` if they're up
+    /// to date. Rebuilding a host tool the step runs counts as a changed input.
+    pub fn generate_rebuild_reason(
+        &self,
+        target: &TargetConfig,
+        rule: &GenerateRule,
+    ) -> Result<Option<(PathBuf, RebuildReason)>> {
+        let command = self.generate_command(target, rule)?;
+        let mut inputs = rule
+            .inputs
+            .iter()
+            .map(|input| self.base_dir.join(input))
+            .collect::<Vec<_>>();
+        inputs.extend(
+            self.generate_tools(target, rule)?
+                .into_iter()
+                .map(|(_, tool)| self.target_output(tool)),
+        );
+
+        let generated_dir = self.generated_dir(target);
+        for output in rule.outputs.iter().map(|output| generated_dir.join(output)) {
+            if let Some(reason) = self.link_rebuild_reason(&output, &command, &inputs, &[])? {
+                return Ok(Some((output, reason)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Run the custom build steps of a target whose outputs are missing or out of date, so
+    /// generated sources and headers are in place before anything is compiled
+    pub fn run_generators(&self, target: &TargetConfig) -> Result<()> {
+        let generated_dir = self.generated_dir(target);
+        for rule in &target.generate {
+            if rule.command.is_empty() || rule.outputs.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Every generate step of target `{}` needs a command and at least one output",
+                    target.name
+                ));
+            }
+
+            match self.generate_rebuild_reason(target, rule)? {
+                Some((output, reason)) => self.explain(&output, &reason),
+                None => {
+                    log::debug!(
+                        "Skipping up-to-date generate step: {}",
+                        rule.command.join(" ")
+                    );
+                    continue;
+                }
+            }
+
+            let outputs = rule
+                .outputs
+                .iter()
+                .map(|output| generated_dir.join(output))
+                .collect::<Vec<_>>();
+            for dir in outputs.iter().filter_map(|output| output.parent()) {
+                self.sh.create_dir(dir)?;
+            }
+
+            let command = self.generate_command(target, rule)?;
+            let (program, args) = command.split_first().unwrap();
+            self.timings.time(
+                StepKind::Generate,
+                &target.name,
+                rule.outputs[0].display(),
+                || {
+                    let _guard = self.sh.push_dir(&self.base_dir);
+                    Ok(cmd!(self.sh, "{program}").args(args).quiet().run()?)
+                },
+            )?;
+
+            for output in &outputs {
+                if !output.exists() {
+                    return Err(anyhow::anyhow!(
+                        "Generate step `{}` of target `{}` didn't write {}",
+                        command.join(" "),
+                        target.name,
+                        output.display()
+                    ));
+                }
+                self.record_command(output, &command);
+            }
+
+            log::info!(
+                "Generated {}",
+                outputs
+                    .iter()
+                    .map(|output| output.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{
+    commands::build::{
+        Builder,
+        probe::{CompilerFamily, companion_tool},
+    },
+    config::{LtoMode, TargetConfig},
+};
+
+impl<'a> Builder<'a> {
+    /// Link-time optimization mode of a target, taking target overrides into account
+    pub fn lto(&self, target: &TargetConfig) -> LtoMode {
+        target
+            .build_overrides
+            .as_ref()
+            .and_then(|overrides| overrides.lto)
+            .unwrap_or(self.build_profile().lto)
+    }
+
+    /// Flags that enable link-time optimization, used for both compiling and linking
+    pub fn lto_flags(&self, target: &TargetConfig) -> Vec<String> {
+        match self.lto(target) {
+            LtoMode::Off => vec![],
+            LtoMode::Full => vec!["-flto".to_string()],
+            LtoMode::Thin => match self.compiler_family(target) {
+                CompilerFamily::Clang => vec!["-flto=thin".to_string()],
+                // GCC has no thin LTO, but partitioning across all cores gets close
+                _ => vec!["-flto=auto".to_string()],
+            },
+        }
+    }
+
+    /// Archiver used to create a target's static library. Objects built with LTO contain
+    /// compiler IR instead of machine code, so they need an archiver that can index it. A
+    /// toolchain's archiver is swapped for its LTO-aware sibling then (e.g.
+    /// `aarch64-linux-gnu-ar` -> `aarch64-linux-gnu-gcc-ar`).
+    pub fn archiver(&self, target: &TargetConfig) -> String {
+        let toolchain_archiver = self.toolchain().and_then(|t| t.archiver.as_ref());
+        if self.lto(target) == LtoMode::Off {
+            return toolchain_archiver
+                .cloned()
+                .unwrap_or_else(|| "ar".to_string());
+        }
+
+        let compiler = self.compiler(target);
+        match (self.compiler_family(target), toolchain_archiver) {
+            (CompilerFamily::Gcc, Some(archiver)) => {
+                companion_tool(archiver, &["gcc-ar", "ar"], "gcc-ar")
+            }
+            (CompilerFamily::Gcc, None) => companion_tool(compiler, &["g++", "gcc"], "gcc-ar"),
+            // llvm-ar handles every target, so a plain archiver is replaced by the one next to
+            // the compiler
+            (CompilerFamily::Clang, Some(archiver)) if archiver.contains("llvm-ar") => {
+                archiver.clone()
+            }
+            (CompilerFamily::Clang, _) => {
+                companion_tool(compiler, &["clang++", "clang"], "llvm-ar")
+            }
+            (CompilerFamily::Unknown, archiver) => {
+                archiver.cloned().unwrap_or_else(|| "ar".to_string())
+            }
+        }
+    }
+}
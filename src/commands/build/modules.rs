@@ -0,0 +1,369 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use xshell::cmd;
+
+use crate::{
+    commands::build::{
+        Builder,
+        explain::modified_time,
+        probe::{CompilerFamily, companion_tool},
+    },
+    config::{TargetConfig, TargetLanguage},
+};
+
+/// Extensions of C++20 module interface units
+pub const MODULE_EXTENSIONS: &[&str] = &["cppm", "ixx", "cxxm", "mpp"];
+
+/// Whether `path` is a module interface unit by its extension
+pub fn is_module_source(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| MODULE_EXTENSIONS.iter().any(|m| ext == *m))
+}
+
+/// Whether a compile command imports or produces modules, so its output depends on more than the
+/// preprocessed source
+pub fn uses_module_flags(command: &[String]) -> bool {
+    command
+        .iter()
+        .any(|arg| arg == "-fmodules-ts" || arg.starts_with("-fmodule-"))
+}
+
+/// Dependency information in the P1689 format written by compiler scanners
+#[derive(Debug, Deserialize)]
+struct P1689 {
+    rules: Vec<P1689Rule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct P1689Rule {
+    #[serde(default)]
+    provides: Vec<P1689Module>,
+    #[serde(default)]
+    requires: Vec<P1689Module>,
+}
+
+#[derive(Debug, Deserialize)]
+struct P1689Module {
+    #[serde(rename = "logical-name")]
+    logical_name: String,
+
+    /// Set for header units, which are looked up like includes
+    #[serde(rename = "lookup-method")]
+    lookup_method: Option<String>,
+}
+
+/// Modules a source file provides and imports
+#[derive(Debug, Clone, Default)]
+struct ModuleUnit {
+    provides: Vec<String>,
+    requires: Vec<String>,
+}
+
+/// Module dependencies between the sources of a target
+#[derive(Debug, Clone)]
+pub struct ModuleGraph {
+    /// Indices of the target's sources in an order where interfaces come before importers
+    pub order: Vec<usize>,
+
+    /// Compiled module interface (BMI) of each module
+    bmis: BTreeMap<String, PathBuf>,
+
+    /// Modules each source provides and imports, by source index
+    units: Vec<ModuleUnit>,
+
+    /// GCC module mapper listing every BMI of the target
+    mapper: PathBuf,
+}
+
+impl ModuleGraph {
+    /// Modules the source at `index` imports, directly or through other modules
+    fn required_modules(&self, index: usize) -> BTreeSet<String> {
+        let providers = self.providers();
+        let mut required = BTreeSet::new();
+        let mut pending = self.units[index].requires.clone();
+        while let Some(module) = pending.pop() {
+            if required.insert(module.clone())
+                && let Some(&provider) = providers.get(&module)
+            {
+                pending.extend(self.units[provider].requires.iter().cloned());
+            }
+        }
+        required
+    }
+
+    /// Source index providing each module
+    fn providers(&self) -> BTreeMap<String, usize> {
+        self.units
+            .iter()
+            .enumerate()
+            .flat_map(|(index, unit)| unit.provides.iter().map(move |m| (m.clone(), index)))
+            .collect()
+    }
+
+    /// BMIs the source at `index` reads, so it's rebuilt when one of them changes
+    pub fn required_bmis(&self, index: usize) -> Vec<PathBuf> {
+        self.required_modules(index)
+            .iter()
+            .filter_map(|module| self.bmis.get(module).cloned())
+            .collect()
+    }
+}
+
+impl<'a> Builder<'a> {
+    /// Whether a target has C++20 module sources that need scanning before they're compiled
+    pub fn uses_modules(&self, target: &TargetConfig, src_files: &[PathBuf]) -> bool {
+        target.language == TargetLanguage::Cpp && src_files.iter().any(|src| is_module_source(src))
+    }
+
+    /// Scan a source for the modules it provides and imports. Results are kept next to the
+    /// object and only redone when the source or configuration changes.
+    fn scan_module_unit(
+        &self,
+        target: &TargetConfig,
+        src: &Path,
+        obj: &Path,
+    ) -> Result<ModuleUnit> {
+        let ddi = obj.with_extension("ddi");
+        let stale = match (modified_time(&ddi)?, modified_time(src)?) {
+            (Some(scanned), Some(modified)) => self.config_updated || scanned < modified,
+            _ => true,
+        };
+
+        if stale {
+            let mut command = self.compiler_command(target);
+            command.extend(self.precompiled_header_flags(target));
+            let compiler = self.compiler_info(target)?;
+            match compiler.family {
+                CompilerFamily::Clang => {
+                    let scanner = companion_tool(
+                        self.compiler(target),
+                        &["clang++", "clang"],
+                        "clang-scan-deps",
+                    );
+                    if is_module_source(src) {
+                        command.extend(["-x".to_string(), "c++-module".to_string()]);
+                    }
+                    command.extend([
+                        "-c".to_string(),
+                        src.display().to_string(),
+                        "-o".to_string(),
+                        obj.display().to_string(),
+                    ]);
+                    let output = cmd!(self.sh, "{scanner} -format=p1689 --")
+                        .args(&command)
+                        .quiet()
+                        .read()?;
+                    self.sh.write_file(&ddi, output)?;
+                }
+                CompilerFamily::Gcc => {
+                    let major = compiler
+                        .version
+                        .split('.')
+                        .next()
+                        .and_then(|major| major.parse::<u32>().ok())
+                        .unwrap_or(0);
+                    if major < 14 {
+                        return Err(anyhow::anyhow!(
+                            "Target `{}` uses C++20 modules, which need GCC 14 or newer to scan (found {})",
+                            target.name,
+                            compiler.version
+                        ));
+                    }
+                    command.extend([
+                        "-fmodules-ts".to_string(),
+                        "-fdeps-format=p1689r5".to_string(),
+                        format!("-fdeps-file={}", ddi.display()),
+                        format!("-fdeps-target={}", obj.display()),
+                        "-MD".to_string(),
+                        "-MT".to_string(),
+                        ddi.display().to_string(),
+                        "-MF".to_string(),
+                        obj.with_extension("ddi.d").display().to_string(),
+                        "-E".to_string(),
+                        "-x".to_string(),
+                        "c++".to_string(),
+                        src.display().to_string(),
+                        "-o".to_string(),
+                        obj.with_extension("ii").display().to_string(),
+                    ]);
+                    let (compiler, args) = command.split_first().unwrap();
+                    cmd!(self.sh, "{compiler}").args(args).quiet().run()?;
+                }
+                CompilerFamily::Unknown => {
+                    return Err(anyhow::anyhow!(
+                        "Target `{}` uses C++20 modules, which need GCC or Clang",
+                        target.name
+                    ));
+                }
+            }
+            log::debug!("Scanned {} for module dependencies", src.display());
+        }
+
+        let contents = self.sh.read_file(&ddi)?;
+        let deps: P1689 = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse module dependencies in {}", ddi.display()))?;
+
+        let mut unit = ModuleUnit::default();
+        for rule in deps.rules {
+            unit.provides
+                .extend(rule.provides.into_iter().map(|m| m.logical_name));
+            for module in rule.requires {
+                if module.lookup_method.is_some() {
+                    return Err(anyhow::anyhow!(
+                        "{} imports header unit {}, which isn't supported",
+                        src.display(),
+                        module.logical_name
+                    ));
+                }
+                unit.requires.push(module.logical_name);
+            }
+        }
+        Ok(unit)
+    }
+
+    /// Scan the sources of a target and order them so every module interface is compiled before
+    /// the sources importing it. Returns `None` for targets without module sources.
+    pub fn module_graph(
+        &self,
+        target: &TargetConfig,
+        src_files: &[PathBuf],
+        obj_files: &[PathBuf],
+    ) -> Result<Option<ModuleGraph>> {
+        if !self.uses_modules(target, src_files) {
+            return Ok(None);
+        }
+
+        let units = src_files
+            .iter()
+            .zip(obj_files.iter())
+            .map(|(src, obj)| self.scan_module_unit(target, src, obj))
+            .collect::<Result<Vec<_>>>()?;
+
+        let module_dir = self.target_internal_dir(target).join("modules");
+        let mut bmis = BTreeMap::new();
+        let mut providers = BTreeMap::new();
+        for (index, unit) in units.iter().enumerate() {
+            for module in &unit.provides {
+                if let Some(other) = providers.insert(module.clone(), index) {
+                    return Err(anyhow::anyhow!(
+                        "Module `{}` is provided by both {} and {}",
+                        module,
+                        src_files[other].display(),
+                        src_files[index].display()
+                    ));
+                }
+                let extension = match self.compiler_family(target) {
+                    CompilerFamily::Clang => "pcm",
+                    _ => "gcm",
+                };
+                bmis.insert(
+                    module.clone(),
+                    module_dir.join(format!("{}.{}", module.replace(':', "-"), extension)),
+                );
+            }
+        }
+
+        // depth-first topological sort, keeping discovery order between independent sources
+        let mut order = vec![];
+        let mut state = vec![0u8; units.len()]; // 0: unvisited, 1: visiting, 2: done
+        fn visit(
+            index: usize,
+            units: &[ModuleUnit],
+            providers: &BTreeMap<String, usize>,
+            src_files: &[PathBuf],
+            state: &mut [u8],
+            order: &mut Vec<usize>,
+        ) -> Result<()> {
+            match state[index] {
+                2 => return Ok(()),
+                1 => {
+                    return Err(anyhow::anyhow!(
+                        "Module import cycle through {}",
+                        src_files[index].display()
+                    ));
+                }
+                _ => {}
+            }
+            state[index] = 1;
+            for module in &units[index].requires {
+                let Some(&provider) = providers.get(module) else {
+                    return Err(anyhow::anyhow!(
+                        "{} imports module `{}`, which no source of its target provides",
+                        src_files[index].display(),
+                        module
+                    ));
+                };
+                visit(provider, units, providers, src_files, state, order)?;
+            }
+            state[index] = 2;
+            order.push(index);
+            Ok(())
+        }
+        for index in 0..units.len() {
+            visit(index, &units, &providers, src_files, &mut state, &mut order)?;
+        }
+
+        // compilers write BMIs without creating their directory
+        self.sh.create_dir(&module_dir)?;
+
+        // GCC finds BMIs through a mapper file, only rewritten when the modules change
+        let mapper = module_dir.join("mapper");
+        if self.compiler_family(target) != CompilerFamily::Clang {
+            let contents: String = bmis
+                .iter()
+                .map(|(module, bmi)| format!("{} {}\n", module, bmi.display()))
+                .collect();
+            if std::fs::read_to_string(&mapper).ok().as_deref() != Some(&contents) {
+                self.sh.write_file(&mapper, contents)?;
+            }
+        }
+
+        Ok(Some(ModuleGraph {
+            order,
+            bmis,
+            units,
+            mapper,
+        }))
+    }
+
+    /// Flags that let the source at `index` import the modules it needs and, for interfaces,
+    /// write its BMI
+    pub fn module_flags(
+        &self,
+        target: &TargetConfig,
+        graph: &ModuleGraph,
+        index: usize,
+        src: &Path,
+    ) -> Vec<String> {
+        let mut flags = vec![];
+        match self.compiler_family(target) {
+            CompilerFamily::Clang => {
+                let unit = &graph.units[index];
+                if let Some(bmi) = unit.provides.first().and_then(|m| graph.bmis.get(m)) {
+                    flags.push(format!("-fmodule-output={}", bmi.display()));
+                    if is_module_source(src) {
+                        flags.extend(["-x".to_string(), "c++-module".to_string()]);
+                    }
+                }
+                for module in graph.required_modules(index) {
+                    if let Some(bmi) = graph.bmis.get(&module) {
+                        flags.push(format!("-fmodule-file={}={}", module, bmi.display()));
+                    }
+                }
+            }
+            _ => {
+                flags.push("-fmodules-ts".to_string());
+                flags.push(format!("-fmodule-mapper={}", graph.mapper.display()));
+                if is_module_source(src) {
+                    flags.extend(["-x".to_string(), "c++".to_string()]);
+                }
+            }
+        }
+        flags
+    }
+}
@@ -0,0 +1,236 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use xshell::cmd;
+
+use crate::{
+    commands::build::{Builder, modules::uses_module_flags},
+    config::CoverageMode,
+};
+
+/// Turn a compile command into one that preprocesses to stdout, dropping `-c` and `-o`. The
+/// depfile is still written as a side effect, so header tracking keeps working on cache hits.
+fn preprocess_command(command: &[String]) -> Vec<String> {
+    let mut preprocess = vec![];
+    let mut args = command.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => {
+                args.next();
+            }
+            "-c" => preprocess.push("-E".to_string()),
+            _ => preprocess.push(arg.clone()),
+        }
+    }
+    preprocess
+}
+
+/// Drop the arguments that only name output files, which differ between profiles without
+/// changing the compiled object
+fn output_independent_args(command: &[String]) -> Vec<&str> {
+    let mut result = vec![];
+    let mut args = command.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "-MF" => {
+                args.next();
+            }
+            _ => result.push(arg.as_str()),
+        }
+    }
+    result
+}
+
+impl<'a> Builder<'a> {
+    /// Key the output of `command` is stored under in the object cache, or `None` if the cache is
+    /// disabled. The key covers the preprocessed source, the compile command and the compiler
+    /// identity. The project directory is left out when it's remapped (see `reproducible`), so
+    /// other checkouts can share objects.
+    pub fn object_cache_key(
+        &self,
+        command: &[String],
+        compiler_id: &str,
+    ) -> Result<Option<String>> {
+        // time traces and coverage notes are written next to the object, so they'd be missing on
+        // a cache hit. objects built with profile data, modules or a clang PCH (which preprocessing
+        // leaves out) depend on more than the source, and a reproducibility check has to compile
+        // everything itself.
+        if !self.config.cache.enabled
+            || uses_module_flags(command)
+            || command.iter().any(|arg| arg == "-include-pch")
+            || self.opts.time_trace
+            || self.build_profile().coverage != CoverageMode::Off
+            || self.opts.pgo.is_some()
+            || self.opts.reproducibility_check
+        {
+            return Ok(None);
+        }
+
+        let preprocess = preprocess_command(command);
+        let (compiler, args) = preprocess.split_first().unwrap();
+        let output = cmd!(self.sh, "{compiler}").args(args).quiet().output()?;
+
+        // objects only stop depending on where the project is once its path is remapped, so
+        // without a remap the project directory is part of the key
+        let base_dir = self.base_dir.to_string_lossy();
+        let remap = format!("-ffile-prefix-map={}=", base_dir);
+        let remapped = command.iter().any(|arg| arg.starts_with(&remap));
+        let normalize = |text: &str| {
+            if remapped {
+                text.replace(&*base_dir, ".")
+            } else {
+                text.to_string()
+            }
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update(compiler_id.as_bytes());
+        hasher.update([0]);
+        if !remapped {
+            hasher.update(base_dir.as_bytes());
+            hasher.update([0]);
+        }
+        for arg in output_independent_args(command) {
+            hasher.update(normalize(arg).as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update(normalize(&String::from_utf8_lossy(&output.stdout)).as_bytes());
+
+        let key = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Ok(Some(key))
+    }
+
+    /// Path of the object stored under `key` in the local cache
+    fn cached_object_path(&self, key: &str) -> Option<PathBuf> {
+        let dir = self.config.cache.local_dir()?;
+        Some(dir.join(&key[..2]).join(format!("{}.o", key)))
+    }
+
+    /// Copy the object stored under `key` to `obj`, returning whether it was found locally or in
+    /// the remote cache
+    pub fn restore_object(&self, key: &str, obj: &Path) -> Result<bool> {
+        if let Some(cached) = self.cached_object_path(key)
+            && cached.is_file()
+        {
+            std::fs::copy(&cached, obj)?;
+            log::debug!("Object cache hit for {}", obj.display());
+            return Ok(true);
+        }
+
+        if let Some(remote) = &self.config.cache.remote
+            && self.download_object(remote, key, obj)?
+        {
+            log::debug!("Remote object cache hit for {}", obj.display());
+
+            // keep a local copy so the next lookup doesn't need the network
+            if let Err(e) = self.store_local_object(key, obj) {
+                log::warn!("Failed to store {} in object cache: {}", obj.display(), e);
+            }
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Store a freshly compiled object in the cache under `key`, uploading it to the remote cache
+    /// unless it's read-only
+    pub fn store_object(&self, key: &str, obj: &Path) -> Result<()> {
+        self.store_local_object(key, obj)?;
+
+        if let Some(remote) = &self.config.cache.remote
+            && !self.config.cache.read_only
+        {
+            self.upload_object(remote, key, obj)?;
+        }
+
+        Ok(())
+    }
+
+    fn store_local_object(&self, key: &str, obj: &Path) -> Result<()> {
+        let Some(cached) = self.cached_object_path(key) else {
+            return Ok(());
+        };
+        self.sh.create_dir(cached.parent().unwrap())?;
+
+        // copy then rename, so a concurrent build never sees a half-written object
+        let partial = cached.with_extension(format!("o.{}.tmp", std::process::id()));
+        std::fs::copy(obj, &partial)?;
+        std::fs::rename(&partial, &cached)?;
+        log::debug!("Stored {} in object cache", obj.display());
+        Ok(())
+    }
+
+    /// Fetch the object stored under `key` from the remote cache into `obj`, returning whether it
+    /// was found
+    fn download_object(&self, remote: &str, key: &str, obj: &Path) -> Result<bool> {
+        let url = remote_object_url(remote, key);
+        let partial = obj.with_extension("o.download");
+
+        let output = cmd!(
+            self.sh,
+            "curl --silent --fail --connect-timeout {REMOTE_TIMEOUT_SECS} --output {partial} {url}"
+        )
+        .quiet()
+        .ignore_status()
+        .output();
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                log::warn!("Failed to query remote object cache: {}", e);
+                return Ok(false);
+            }
+        };
+
+        if !output.status.success() {
+            // a miss is expected, anything else is worth mentioning
+            if !matches!(
+                output.status.code(),
+                Some(CURL_HTTP_ERROR | CURL_FILE_NOT_FOUND)
+            ) {
+                log::warn!(
+                    "Failed to download {} from remote object cache: {}",
+                    url,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            let _ = std::fs::remove_file(&partial);
+            return Ok(false);
+        }
+
+        std::fs::rename(&partial, obj)?;
+        Ok(true)
+    }
+
+    fn upload_object(&self, remote: &str, key: &str, obj: &Path) -> Result<()> {
+        let url = remote_object_url(remote, key);
+        cmd!(
+            self.sh,
+            "curl --silent --show-error --fail --connect-timeout {REMOTE_TIMEOUT_SECS} --upload-file {obj} {url}"
+        )
+        .quiet()
+        .ignore_stdout()
+        .run()?;
+        log::debug!("Uploaded {} to {}", obj.display(), url);
+        Ok(())
+    }
+}
+
+/// Seconds to wait for the remote cache to accept a connection
+const REMOTE_TIMEOUT_SECS: &str = "5";
+
+/// Exit code curl uses for HTTP errors (like a 404 on a cache miss) when run with `--fail`
+const CURL_HTTP_ERROR: i32 = 22;
+
+/// Exit code curl uses when a `file://` URL doesn't exist
+const CURL_FILE_NOT_FOUND: i32 = 37;
+
+/// URL of a blob in the remote cache. Blobs are stored flat so any server that can GET and PUT
+/// files (or a `file://` directory) works as a cache.
+fn remote_object_url(remote: &str, key: &str) -> String {
+    format!("{}/{}.o", remote.trim_end_matches('/'), key)
+}
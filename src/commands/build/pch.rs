@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use xshell::cmd;
+
+use crate::{
+    commands::build::{Builder, output_args, probe::CompilerFamily, timings::StepKind},
+    config::{TargetConfig, TargetLanguage},
+};
+
+/// Files making up the precompiled header of a target
+#[derive(Debug, Clone)]
+pub struct PrecompiledHeader {
+    /// The header as configured
+    pub header: PathBuf,
+
+    /// Header in the build directory that includes the configured one. Compilers look for the
+    /// precompiled output next to the header being included, so we include this one instead.
+    pub wrapper: PathBuf,
+
+    /// Compiled header (`.gch` for GCC, `.pch` for Clang)
+    pub output: PathBuf,
+}
+
+impl<'a> Builder<'a> {
+    /// The precompiled header of a target, if it has one
+    pub fn precompiled_header(&self, target: &TargetConfig) -> Option<PrecompiledHeader> {
+        let header = self.base_dir.join(target.precompiled_header.as_ref()?);
+        let wrapper = self
+            .target_internal_dir(target)
+            .join("pch")
+            .join(header.file_name()?);
+
+        let extension = match self.compiler_family(target) {
+            CompilerFamily::Clang => "pch",
+            _ => "gch",
+        };
+        let mut output = wrapper.clone().into_os_string();
+        output.push(format!(".{}", extension));
+
+        Some(PrecompiledHeader {
+            header,
+            wrapper,
+            output: PathBuf::from(output),
+        })
+    }
+
+    /// Files every object of a target depends on without the compiler reporting them
+    pub fn precompiled_header_inputs(&self, target: &TargetConfig) -> Vec<PathBuf> {
+        self.precompiled_header(target)
+            .map(|pch| vec![pch.output])
+            .unwrap_or_default()
+    }
+
+    /// Flags that make a source file use the precompiled header of its target
+    pub fn precompiled_header_flags(&self, target: &TargetConfig) -> Vec<String> {
+        let Some(pch) = self.precompiled_header(target) else {
+            return vec![];
+        };
+
+        match self.compiler_family(target) {
+            CompilerFamily::Clang => {
+                vec!["-include-pch".to_string(), pch.output.display().to_string()]
+            }
+            // GCC silently falls back to the header itself if the PCH can't be used
+            _ => vec![
+                "-Winvalid-pch".to_string(),
+                "-include".to_string(),
+                pch.wrapper.display().to_string(),
+            ],
+        }
+    }
+
+    /// Command that compiles the precompiled header of a target, with the same flags as its
+    /// source files
+    pub fn precompiled_header_command(
+        &self,
+        target: &TargetConfig,
+        pch: &PrecompiledHeader,
+    ) -> Vec<String> {
+        let language = match target.language {
+            TargetLanguage::C => "c-header",
+            TargetLanguage::Cpp => "c++-header",
+        };
+
+        let mut command = self.compiler_command(target);
+        command.extend(["-x".to_string(), language.to_string()]);
+        command.extend(output_args(&pch.wrapper, &pch.output));
+        command
+    }
+
+    /// Compile the precompiled header of a target if it's missing or out of date
+    pub fn build_precompiled_header(&self, target: &TargetConfig, compiler_id: &str) -> Result<()> {
+        let Some(pch) = self.precompiled_header(target) else {
+            return Ok(());
+        };
+        if !pch.header.is_file() {
+            return Err(anyhow::anyhow!(
+                "Precompiled header {} of target `{}` does not exist",
+                pch.header.display(),
+                target.name
+            ));
+        }
+
+        // only touch the wrapper when it changes, so it doesn't look newer than the output
+        let wrapper_contents = format!("#include \"{}\"\n", pch.header.display());
+        if std::fs::read_to_string(&pch.wrapper).ok().as_deref() != Some(&wrapper_contents) {
+            self.sh.write_file(&pch.wrapper, wrapper_contents)?;
+        }
+
+        let command = self.precompiled_header_command(target, &pch);
+        match self.object_rebuild_reason(&pch.wrapper, &pch.output, &command, compiler_id, &[])? {
+            Some(reason) => self.explain(&pch.output, &reason),
+            None => {
+                log::debug!(
+                    "Skipping unchanged precompiled header: {}",
+                    pch.header.display()
+                );
+                return Ok(());
+            }
+        }
+
+        let (compiler, args) = command.split_first().unwrap();
+        self.timings.time(
+            StepKind::Compile,
+            &target.name,
+            pch.header.display(),
+            || Ok(cmd!(self.sh, "{compiler}").args(args).quiet().run()?),
+        )?;
+        self.record_command(&pch.output, &command);
+        self.file_cache
+            .lock()
+            .unwrap()
+            .compilers
+            .insert(pch.output.clone(), compiler_id.to_string());
+
+        log::info!("Precompiled {}", pch.header.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use xshell::cmd;
+
+use crate::{
+    commands::build::{
+        Builder,
+        probe::{CompilerFamily, companion_tool},
+    },
+    config::TargetConfig,
+};
+
+/// Phase of the profile-guided optimization workflow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PgoPhase {
+    /// Instrumented build that writes profile data when run
+    Generate,
+
+    /// Optimized build that uses the collected profile data
+    Use,
+}
+
+impl<'a> Builder<'a> {
+    /// Directory the profile data of `jfb pgo` is collected in, shared by both phases
+    pub fn pgo_data_dir(&self) -> PathBuf {
+        self.profile_dir()
+            .with_file_name(format!("{}-pgo-data", self.opts.profile))
+    }
+
+    /// Merged Clang profile the optimized build uses
+    fn pgo_profdata(&self) -> PathBuf {
+        self.pgo_data_dir().join("merged.profdata")
+    }
+
+    /// Flags for the current PGO phase, used for both compiling and linking
+    pub fn pgo_flags(&self, target: &TargetConfig) -> Vec<String> {
+        let Some(phase) = self.opts.pgo else {
+            return vec![];
+        };
+
+        match (phase, self.compiler_family(target)) {
+            (PgoPhase::Generate, CompilerFamily::Clang) => vec![format!(
+                "-fprofile-generate={}",
+                self.pgo_data_dir().display()
+            )],
+            // GCC writes its counters next to each object, they're moved over before the
+            // optimized build
+            (PgoPhase::Generate, _) => vec!["-fprofile-generate".to_string()],
+            (PgoPhase::Use, CompilerFamily::Clang) => {
+                vec![format!("-fprofile-use={}", self.pgo_profdata().display())]
+            }
+            // code the training run never reached has no profile, which is fine
+            (PgoPhase::Use, _) => vec![
+                "-fprofile-use".to_string(),
+                "-Wno-missing-profile".to_string(),
+            ],
+        }
+    }
+
+    /// Check that every target is built by a compiler we know how to do PGO with
+    pub fn check_pgo_support(&self) -> Result<()> {
+        for target in &self.config.targets {
+            let info = self.compiler_info(target)?;
+            if info.family == CompilerFamily::Unknown {
+                return Err(anyhow::anyhow!(
+                    "Profile-guided optimization needs GCC or Clang, but target `{}` is built with an unknown compiler",
+                    target.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the profile data of previous training runs of the instrumented build
+    pub fn reset_pgo_data(&self) -> Result<()> {
+        for target in &self.config.targets {
+            let (_, obj_files) = self.target_sources(target)?;
+            for counters in obj_files.iter().map(|obj| obj.with_extension("gcda")) {
+                if counters.exists() {
+                    self.sh.remove_path(counters)?;
+                }
+            }
+        }
+        self.sh.remove_path(self.pgo_data_dir())?;
+        Ok(())
+    }
+
+    /// Gather the profile data written by the `instrumented` build's training run where this
+    /// (optimized) build will look for it
+    pub fn import_pgo_data(&self, instrumented: &Builder) -> Result<()> {
+        for target in &self.config.targets {
+            match self.compiler_family(target) {
+                CompilerFamily::Clang => {}
+                _ => {
+                    let (_, instrumented_objs) = instrumented.target_sources(target)?;
+                    let (_, obj_files) = self.target_sources(target)?;
+                    self.sh.create_dir(self.target_out_dir(target))?;
+                    for (from, to) in instrumented_objs.iter().zip(obj_files.iter()) {
+                        let counters = from.with_extension("gcda");
+                        if counters.is_file() {
+                            self.sh.copy_file(&counters, to.with_extension("gcda"))?;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Clang's raw profiles have to be merged into one before they can be used
+        let Some(target) = self
+            .config
+            .targets
+            .iter()
+            .find(|target| self.compiler_family(target) == CompilerFamily::Clang)
+        else {
+            return Ok(());
+        };
+
+        let data_dir = self.pgo_data_dir();
+        let profiles = if data_dir.is_dir() {
+            self.sh
+                .read_dir(&data_dir)?
+                .into_iter()
+                .filter(|path| path.extension().is_some_and(|ext| ext == "profraw"))
+                .collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+        if profiles.is_empty() {
+            return Err(anyhow::anyhow!(
+                "The training run didn't write any profile data to {}",
+                data_dir.display()
+            ));
+        }
+
+        let profdata_tool = companion_tool(
+            self.compiler(target),
+            &["clang++", "clang"],
+            "llvm-profdata",
+        );
+        let profdata = self.pgo_profdata();
+        cmd!(self.sh, "{profdata_tool} merge -o {profdata}")
+            .args(&profiles)
+            .quiet()
+            .run()?;
+        log::info!(
+            "Merged {} profile(s) into {}",
+            profiles.len(),
+            profdata.display()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use xshell::cmd;
+
+use crate::{
+    commands::build::{Builder, explain::modified_time},
+    config::{TargetConfig, TargetLanguage},
+};
+
+/// Family of a C/C++ compiler
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompilerFamily {
+    Gcc,
+    Clang,
+    Unknown,
+}
+
+impl fmt::Display for CompilerFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompilerFamily::Gcc => write!(f, "GCC"),
+            CompilerFamily::Clang => write!(f, "Clang"),
+            CompilerFamily::Unknown => write!(f, "unknown compiler"),
+        }
+    }
+}
+
+/// What we learned about a compiler by probing it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompilerInfo {
+    /// Resolved path of the compiler executable
+    pub path: PathBuf,
+
+    /// Modified time of the executable when it was probed, so upgrades are noticed
+    pub modified: Option<SystemTime>,
+
+    pub family: CompilerFamily,
+
+    /// Compiler version, e.g. `12.2.0`
+    pub version: String,
+
+    /// Target the compiler produces code for, as reported by `-dumpmachine`
+    pub target: String,
+
+    /// Sets of flags that have been checked against the compiler, and whether they're supported
+    #[serde(default)]
+    pub checked_flags: HashMap<String, bool>,
+}
+
+impl CompilerInfo {
+    /// Identity of the compiler, used to notice when objects were built by a different one
+    pub fn id(&self) -> String {
+        format!(
+            "{} {} ({}) at {}",
+            self.family,
+            self.version,
+            self.target,
+            self.path.display()
+        )
+    }
+}
+
+/// Probed compilers, keyed by the compiler command and toolchain arguments
+pub type ProbeCache = HashMap<String, CompilerInfo>;
+
+/// Find an executable the same way the shell would
+fn find_executable(name: &str, base_dir: &Path) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.components().count() > 1 {
+        let path = base_dir.join(path);
+        return path.is_file().then_some(path);
+    }
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Swap the compiler name in `compiler` for one of its companion tools, keeping the directory,
+/// target prefix and version suffix (e.g. `aarch64-linux-gnu-gcc-12` -> `aarch64-linux-gnu-gcov-12`)
+pub fn companion_tool(compiler: &str, names: &[&str], tool: &str) -> String {
+    let path = Path::new(compiler);
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return tool.to_string();
+    };
+
+    for name in names {
+        if let Some(index) = file_name.rfind(name) {
+            let renamed = format!(
+                "{}{}{}",
+                &file_name[..index],
+                tool,
+                &file_name[index + name.len()..]
+            );
+            return path.with_file_name(renamed).display().to_string();
+        }
+    }
+    tool.to_string()
+}
+
+impl<'a> Builder<'a> {
+    /// Probe the compiler used for a target, reusing the cached result when the compiler
+    /// executable hasn't changed
+    pub fn compiler_info(&self, target: &TargetConfig) -> Result<CompilerInfo> {
+        let compiler = self.compiler(target);
+        // whether the triple applies depends on the family being probed, so it's only part of
+        // the key here
+        let sysroot_args = self.sysroot_args();
+        let triple_args = self.target_triple_args(CompilerFamily::Clang);
+        let key = std::iter::once(compiler.to_string())
+            .chain(sysroot_args.iter().cloned())
+            .chain(triple_args.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let path = find_executable(compiler, &self.base_dir).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Compiler `{}` for target `{}` was not found on PATH",
+                compiler,
+                target.name
+            )
+        })?;
+        let modified = modified_time(&path)?;
+
+        let mut probes = self.probes.lock().unwrap();
+        if let Some(info) = probes.get(&key)
+            && info.path == path
+            && info.modified == modified
+        {
+            return Ok(info.clone());
+        }
+
+        log::debug!("Probing compiler `{}`", key);
+
+        // the predefined macros tell us which compiler this is and its version
+        let macros = cmd!(self.sh, "{compiler}")
+            .args(&sysroot_args)
+            .args(["-x", "c", "-E", "-dM", "-"])
+            .stdin("")
+            .quiet()
+            .read()?;
+        let macros = macros
+            .lines()
+            .filter_map(|line| line.strip_prefix("#define "))
+            .filter_map(|line| line.split_once(' '))
+            .collect::<HashMap<_, _>>();
+        let version_of = |major: &str, minor: &str, patch: &str| {
+            [major, minor, patch]
+                .iter()
+                .map(|name| macros.get(name).copied().unwrap_or("0"))
+                .collect::<Vec<_>>()
+                .join(".")
+        };
+
+        let (family, version) = if macros.contains_key("__clang__") {
+            (
+                CompilerFamily::Clang,
+                version_of("__clang_major__", "__clang_minor__", "__clang_patchlevel__"),
+            )
+        } else if macros.contains_key("__GNUC__") {
+            (
+                CompilerFamily::Gcc,
+                version_of("__GNUC__", "__GNUC_MINOR__", "__GNUC_PATCHLEVEL__"),
+            )
+        } else {
+            (CompilerFamily::Unknown, String::from("unknown"))
+        };
+
+        let machine = cmd!(self.sh, "{compiler}")
+            .args(&sysroot_args)
+            .args(self.target_triple_args(family))
+            .arg("-dumpmachine")
+            .quiet()
+            .ignore_status()
+            .read()
+            .unwrap_or_default();
+
+        let info = CompilerInfo {
+            path,
+            modified,
+            family,
+            version,
+            target: machine.trim().to_string(),
+            checked_flags: HashMap::new(),
+        };
+        log::debug!("Detected compiler: {}", info.id());
+
+        probes.insert(key, info.clone());
+        Ok(info)
+    }
+
+    /// Family of the compiler of a target, if it can be probed
+    pub fn compiler_family(&self, target: &TargetConfig) -> CompilerFamily {
+        self.compiler_info(target)
+            .map(|info| info.family)
+            .unwrap_or(CompilerFamily::Unknown)
+    }
+
+    /// Check that the compiler of a target supports its language standard and flags, so we fail
+    /// with a clear message instead of on the first source file
+    pub fn check_compiler(&self, target: &TargetConfig, info: &CompilerInfo) -> Result<()> {
+        let flags = self.compile_flags(target);
+        if self.flags_supported(target, info, &flags)? {
+            return Ok(());
+        }
+
+        // narrow it down to the flags the compiler rejects
+        let unsupported = flags
+            .iter()
+            .filter(|flag| {
+                !self
+                    .flags_supported(target, info, std::slice::from_ref(flag))
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if unsupported.is_empty() {
+            Err(anyhow::anyhow!(
+                "{} {} rejects the flags of target `{}`: {}",
+                info.family,
+                info.version,
+                target.name,
+                flags.join(" ")
+            ))
+        } else {
+            Err(anyhow::anyhow!(
+                "{} {} does not support {} (needed by target `{}`)",
+                info.family,
+                info.version,
+                unsupported
+                    .iter()
+                    .map(|flag| format!("`{}`", flag))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                target.name
+            ))
+        }
+    }
+
+    /// Whether the compiler accepts the given flags, compiling a trivial translation unit
+    pub fn flags_supported(
+        &self,
+        target: &TargetConfig,
+        info: &CompilerInfo,
+        flags: &[String],
+    ) -> Result<bool> {
+        let language = match target.language {
+            TargetLanguage::C => "c",
+            TargetLanguage::Cpp => "c++",
+        };
+        let key = format!("{} {}", language, flags.join(" "));
+        let cached = self
+            .probes
+            .lock()
+            .unwrap()
+            .values()
+            .find(|probed| probed.path == info.path)
+            .and_then(|probed| probed.checked_flags.get(&key).copied());
+        if let Some(supported) = cached {
+            return Ok(supported);
+        }
+
+        let compiler = self.compiler(target);
+        let output = cmd!(self.sh, "{compiler}")
+            .args(flags)
+            .args(["-fsyntax-only", "-x", language, "-"])
+            .stdin("int jfb_probe;\n")
+            .quiet()
+            .ignore_status()
+            .output()?;
+        let supported = output.status.success();
+
+        for probed in self.probes.lock().unwrap().values_mut() {
+            if probed.path == info.path {
+                probed.checked_flags.insert(key.clone(), supported);
+            }
+        }
+
+        Ok(supported)
+    }
+}
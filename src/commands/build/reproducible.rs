@@ -0,0 +1,138 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use xshell::cmd;
+
+use crate::{
+    commands::build::{BuildOpts, Builder, build_targets},
+    config::{Args, Config},
+};
+
+impl<'a> Builder<'a> {
+    /// Whether the active profile asks for reproducible output
+    pub fn reproducible(&self) -> bool {
+        self.build_profile().reproducible
+    }
+
+    /// Flags that keep the project's location out of objects (debug info, `__FILE__`, etc.)
+    pub fn reproducible_flags(&self) -> Vec<String> {
+        if !self.reproducible() {
+            return vec![];
+        }
+        let mut flags = vec![format!("-ffile-prefix-map={}=.", self.base_dir.display())];
+
+        // the second build of a reproducibility check writes to its own directory, so paths
+        // into it (generated sources, unity sources, precompiled headers) are mapped to where the
+        // regular build puts them. Later maps take precedence over the project one.
+        if self.opts.reproducibility_check {
+            let regular = self.profile_dir_with(false, true);
+            let regular = regular.strip_prefix(&self.base_dir).unwrap_or(&regular);
+            flags.push(format!(
+                "-ffile-prefix-map={}=./{}",
+                self.profile_dir_with(false, false).display(),
+                regular.display()
+            ));
+        }
+        flags
+    }
+
+    /// Timestamp to embed in place of the current time, from `SOURCE_DATE_EPOCH`
+    pub fn source_date_epoch(&self) -> Option<u64> {
+        self.sh.var("SOURCE_DATE_EPOCH").ok()?.trim().parse().ok()
+    }
+
+    /// Make sure compilers see a `SOURCE_DATE_EPOCH` under a reproducible profile, defaulting to
+    /// the time of the last commit, so `__DATE__` and `__TIME__` don't depend on when we build
+    pub fn init_source_date_epoch(&self) {
+        if !self.reproducible() || self.sh.var_os("SOURCE_DATE_EPOCH").is_some() {
+            return;
+        }
+
+        let base_dir = &self.base_dir;
+        let commit_time = cmd!(self.sh, "git -C {base_dir} log -1 --format=%ct")
+            .quiet()
+            .ignore_stderr()
+            .read();
+        match commit_time {
+            Ok(time) if time.trim().parse::<u64>().is_ok() => {
+                log::debug!("Using SOURCE_DATE_EPOCH={} from the last commit", time);
+                self.sh.set_var("SOURCE_DATE_EPOCH", time.trim());
+            }
+            _ => log::debug!("SOURCE_DATE_EPOCH is not set and there's no commit to take it from"),
+        }
+    }
+
+    /// Every artifact the current profile produces, with its path relative to the profile
+    /// directory
+    fn artifacts(&self) -> Vec<(PathBuf, PathBuf)> {
+        let profile_dir = self.profile_dir();
+        self.config
+            .targets
+            .iter()
+            .filter(|target| !target.host_tool)
+            .map(|target| self.target_output(target))
+            .filter(|output| output.is_file())
+            .map(|output| {
+                let relative = output.strip_prefix(&profile_dir).unwrap().to_path_buf();
+                (relative, output)
+            })
+            .collect()
+    }
+}
+
+/// Build everything a second time from scratch in a separate directory, then check that every
+/// artifact came out byte-for-byte the same as in the regular build
+pub fn verify_reproducible(
+    args: &Args,
+    config: &Config,
+    opts: &BuildOpts,
+    base_dir: &Path,
+) -> Result<()> {
+    let first = Builder::new(args, config, opts, base_dir)?;
+    if !first.reproducible() {
+        log::warn!(
+            "Profile `{}` doesn't set `reproducible = true`, so its artifacts will likely differ",
+            opts.profile
+        );
+    }
+
+    let check_opts = BuildOpts {
+        verify_reproducible: false,
+        reproducibility_check: true,
+        ..opts.clone()
+    };
+
+    // nothing from an earlier check may be reused
+    let check_dir = Builder::new(args, config, &check_opts, base_dir)?.profile_dir();
+    if check_dir.exists() {
+        std::fs::remove_dir_all(&check_dir)?;
+    }
+
+    log::info!("Building again in {} to compare", check_dir.display());
+    build_targets(args, config, &check_opts, base_dir)?;
+
+    let mut differing = 0;
+    let artifacts = first.artifacts();
+    for (relative, output) in &artifacts {
+        let rebuilt = check_dir.join(relative);
+        if std::fs::read(output)? != std::fs::read(&rebuilt)? {
+            log::error!(
+                "{} differs between builds (compare with {})",
+                output.display(),
+                rebuilt.display()
+            );
+            differing += 1;
+        }
+    }
+
+    if differing > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} artifact(s) are not reproducible",
+            differing,
+            artifacts.len()
+        ));
+    }
+
+    log::info!("All {} artifact(s) are reproducible", artifacts.len());
+    Ok(())
+}
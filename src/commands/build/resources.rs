@@ -0,0 +1,228 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{
+    commands::build::{Builder, explain::RebuildReason},
+    config::{TargetConfig, TargetLanguage},
+};
+
+/// Whether a file name matches a single path component of a glob, with `*` and `?` wildcards
+fn matches_component(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            matches_component(&pattern[1..], name)
+                || (!name.is_empty() && matches_component(pattern, &name[1..]))
+        }
+        (Some('?'), Some(_)) => matches_component(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => matches_component(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Collect the files under `dir` matching the remaining glob components, where `**` matches any
+/// number of directories
+fn glob_in(dir: &Path, components: &[&str], files: &mut Vec<PathBuf>) -> Result<()> {
+    let Some((first, rest)) = components.split_first() else {
+        if dir.is_file() {
+            files.push(dir.to_path_buf());
+        }
+        return Ok(());
+    };
+
+    if !first.contains(['*', '?']) {
+        return glob_in(&dir.join(first), rest, files);
+    }
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort();
+
+    if *first == "**" {
+        glob_in(dir, rest, files)?;
+        for entry in entries.iter().filter(|entry| entry.is_dir()) {
+            glob_in(entry, components, files)?;
+        }
+        return Ok(());
+    }
+
+    let pattern = first.chars().collect::<Vec<_>>();
+    for entry in entries {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy();
+        if matches_component(&pattern, &name.chars().collect::<Vec<_>>()) {
+            glob_in(&entry, rest, files)?;
+        }
+    }
+    Ok(())
+}
+
+/// C identifier a resource is exposed as, derived from its path (`assets/logo.png` ->
+/// `assets_logo_png`)
+fn resource_symbol(relative: &Path) -> String {
+    let mut symbol = relative
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if symbol.starts_with(|c: char| c.is_ascii_digit()) {
+        symbol.insert(0, '_');
+    }
+    symbol
+}
+
+/// Generated files a target's resources are embedded through
+pub struct ResourceFiles {
+    /// Header declaring each resource
+    pub header: PathBuf,
+
+    /// Source defining each resource as a byte array
+    pub source: PathBuf,
+}
+
+impl<'a> Builder<'a> {
+    /// The generated header and source of a target's resources, if it has any
+    pub fn resource_files(&self, target: &TargetConfig) -> Option<ResourceFiles> {
+        if target.resources.is_empty() {
+            return None;
+        }
+
+        let extension = match target.language {
+            TargetLanguage::C => "c",
+            TargetLanguage::Cpp => "cpp",
+        };
+        let generated_dir = self.generated_dir(target);
+        Some(ResourceFiles {
+            header: generated_dir.join("resources.h"),
+            source: generated_dir.join(format!("resources.{}", extension)),
+        })
+    }
+
+    /// Files matching the resource patterns of a target, in a stable order
+    fn resource_paths(&self, target: &TargetConfig) -> Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for pattern in &target.resources {
+            let components = pattern.split('/').collect::<Vec<_>>();
+            let before = paths.len();
+            glob_in(&self.base_dir, &components, &mut paths)?;
+            if paths.len() == before {
+                log::warn!(
+                    "Resource pattern `{}` of target `{}` doesn't match any files",
+                    pattern,
+                    target.name
+                );
+            }
+        }
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    /// Why the embedded resources of a target need to be regenerated, or `None` if they're up
+    /// to date. The list of resources stands in for a command, so adding or removing one counts
+    /// as a change.
+    pub fn resources_rebuild_reason(&self, target: &TargetConfig) -> Result<Option<RebuildReason>> {
+        let Some(files) = self.resource_files(target) else {
+            return Ok(None);
+        };
+        let paths = self.resource_paths(target)?;
+        let listing = paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+        if !files.header.exists() {
+            return Ok(Some(RebuildReason::OutputMissing));
+        }
+        self.link_rebuild_reason(&files.source, &listing, &paths, &[])
+    }
+
+    /// Generate the source and header embedding a target's resources if any of them changed.
+    /// The header is only rewritten when the set of resources changes, so the sources including
+    /// it aren't recompiled for every edit of a resource.
+    pub fn write_resources(&self, target: &TargetConfig) -> Result<()> {
+        let Some(files) = self.resource_files(target) else {
+            return Ok(());
+        };
+        match self.resources_rebuild_reason(target)? {
+            Some(reason) => self.explain(&files.source, &reason),
+            None => {
+                log::debug!("Skipping unchanged resources of `{}`", target.name);
+                return Ok(());
+            }
+        }
+
+        let paths = self.resource_paths(target)?;
+        let mut symbols: Vec<(String, &Path)> = vec![];
+        for path in &paths {
+            let relative = path.strip_prefix(&self.base_dir).unwrap_or(path);
+            let symbol = resource_symbol(relative);
+            if let Some((_, other)) = symbols.iter().find(|(s, _)| *s == symbol) {
+                return Err(anyhow::anyhow!(
+                    "Resources {} and {} would both be named `{}`",
+                    other.display(),
+                    relative.display(),
+                    symbol
+                ));
+            }
+            symbols.push((symbol, relative));
+        }
+
+        let mut header = String::from(
+            "/* Generated by jfb from the target's resources, do not edit */\n#pragma once\n\n#include <stddef.h>\n\n#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n",
+        );
+        for (symbol, _) in &symbols {
+            writeln!(header, "extern const unsigned char {}[];", symbol)?;
+            writeln!(header, "extern const size_t {}_len;", symbol)?;
+        }
+        header.push_str("\n#ifdef __cplusplus\n}\n#endif\n");
+
+        let mut source = String::from(
+            "/* Generated by jfb from the target's resources, do not edit */\n#include \"resources.h\"\n",
+        );
+        for (symbol, relative) in &symbols {
+            let bytes = std::fs::read(self.base_dir.join(relative))?;
+            writeln!(source, "\n/* {} */", relative.display())?;
+            writeln!(source, "const unsigned char {}[] = {{", symbol)?;
+            for line in bytes.chunks(12) {
+                let line = line
+                    .iter()
+                    .map(|byte| format!("0x{:02x},", byte))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                writeln!(source, "    {}", line)?;
+            }
+            // C doesn't allow empty arrays
+            if bytes.is_empty() {
+                source.push_str("    0\n");
+            }
+            source.push_str("};\n");
+            writeln!(source, "const size_t {}_len = {};", symbol, bytes.len())?;
+        }
+
+        self.sh.create_dir(self.generated_dir(target))?;
+        if std::fs::read_to_string(&files.header).ok().as_deref() != Some(&header) {
+            self.sh.write_file(&files.header, header)?;
+        }
+        self.sh.write_file(&files.source, source)?;
+
+        let listing = paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+        self.record_command(&files.source, &listing);
+
+        log::info!(
+            "Embedded {} resource(s) into {}",
+            paths.len(),
+            files.source.display()
+        );
+        Ok(())
+    }
+}
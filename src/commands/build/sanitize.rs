@@ -0,0 +1,112 @@
+use anyhow::Result;
+
+use crate::commands::build::Builder;
+
+/// Sanitizers we know how to build with
+const KNOWN_SANITIZERS: &[&str] = &[
+    "address",
+    "hwaddress",
+    "undefined",
+    "thread",
+    "memory",
+    "leak",
+];
+
+/// Pairs of sanitizers that can't be combined in a single build
+const INCOMPATIBLE_SANITIZERS: &[(&str, &str)] = &[
+    ("address", "thread"),
+    ("address", "memory"),
+    ("address", "hwaddress"),
+    ("thread", "memory"),
+    ("thread", "leak"),
+    ("memory", "leak"),
+    ("hwaddress", "thread"),
+    ("hwaddress", "memory"),
+];
+
+/// Runtime options for each sanitizer, set when running sanitized executables unless the
+/// environment already has them
+const SANITIZER_ENV: &[(&str, &str, &str)] = &[
+    (
+        "address",
+        "ASAN_OPTIONS",
+        "abort_on_error=1:detect_leaks=1:check_initialization_order=1:strict_string_checks=1",
+    ),
+    ("hwaddress", "HWASAN_OPTIONS", "abort_on_error=1"),
+    (
+        "undefined",
+        "UBSAN_OPTIONS",
+        "print_stacktrace=1:halt_on_error=1",
+    ),
+    (
+        "thread",
+        "TSAN_OPTIONS",
+        "halt_on_error=1:second_deadlock_stack=1",
+    ),
+    ("memory", "MSAN_OPTIONS", "abort_on_error=1"),
+    ("leak", "LSAN_OPTIONS", "report_objects=1"),
+];
+
+/// Check that a set of sanitizers is known and can be combined
+pub fn validate_sanitizers(sanitizers: &[String]) -> Result<()> {
+    for sanitizer in sanitizers {
+        if !KNOWN_SANITIZERS.contains(&sanitizer.as_str()) {
+            return Err(anyhow::anyhow!(
+                "Unknown sanitizer `{}` (known sanitizers: {})",
+                sanitizer,
+                KNOWN_SANITIZERS.join(", ")
+            ));
+        }
+    }
+
+    for (a, b) in INCOMPATIBLE_SANITIZERS {
+        if sanitizers.iter().any(|s| s == a) && sanitizers.iter().any(|s| s == b) {
+            return Err(anyhow::anyhow!(
+                "The `{}` and `{}` sanitizers can't be used together",
+                a,
+                b
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+impl<'a> Builder<'a> {
+    /// Sanitizers to build with, from `--sanitize` or the build profile, sorted and deduplicated
+    pub fn sanitizers(&self) -> Vec<String> {
+        let mut sanitizers = if self.opts.sanitize.is_empty() {
+            self.build_profile().sanitizers.clone()
+        } else {
+            self.opts.sanitize.clone()
+        };
+        sanitizers.sort();
+        sanitizers.dedup();
+        sanitizers
+    }
+
+    /// Flags that enable the sanitizers, used for both compiling and linking
+    pub fn sanitizer_flags(&self) -> Vec<String> {
+        let sanitizers = self.sanitizers();
+        if sanitizers.is_empty() {
+            return vec![];
+        }
+
+        vec![
+            format!("-fsanitize={}", sanitizers.join(",")),
+            "-fno-omit-frame-pointer".to_string(),
+        ]
+    }
+
+    /// Environment variables to run sanitized executables with
+    pub fn sanitizer_env(&self) -> Vec<(&'static str, &'static str)> {
+        let sanitizers = self.sanitizers();
+        SANITIZER_ENV
+            .iter()
+            .filter(|(sanitizer, var, _)| {
+                sanitizers.iter().any(|s| s == sanitizer) && std::env::var_os(var).is_none()
+            })
+            .map(|(_, var, value)| (*var, *value))
+            .collect()
+    }
+}
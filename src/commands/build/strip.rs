@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    commands::build::{
+        Builder,
+        probe::{CompilerFamily, companion_tool},
+    },
+    config::{StripMode, TargetConfig, TargetType},
+};
+
+/// Path of the separate debug info file of an executable
+pub fn debug_file_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".debug");
+    PathBuf::from(path)
+}
+
+/// Drop a version suffix from a tool name (`x86_64-linux-gnu-objcopy-12` ->
+/// `x86_64-linux-gnu-objcopy`), since binutils aren't versioned like GCC
+fn without_version(tool: &str) -> String {
+    let path = Path::new(tool);
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return tool.to_string();
+    };
+    match file_name.rsplit_once('-') {
+        Some((name, version))
+            if !version.is_empty() && version.chars().all(|c| c.is_ascii_digit() || c == '.') =>
+        {
+            path.with_file_name(name).display().to_string()
+        }
+        _ => tool.to_string(),
+    }
+}
+
+impl<'a> Builder<'a> {
+    /// What to strip from a target's executable, taking target overrides into account
+    pub fn strip_mode(&self, target: &TargetConfig) -> StripMode {
+        target
+            .build_overrides
+            .as_ref()
+            .and_then(|overrides| overrides.strip)
+            .unwrap_or(self.build_profile().strip)
+    }
+
+    /// Whether a target's debug info goes into a separate file, taking target overrides into
+    /// account
+    pub fn split_debuginfo(&self, target: &TargetConfig) -> bool {
+        target
+            .build_overrides
+            .as_ref()
+            .and_then(|overrides| overrides.split_debuginfo)
+            .unwrap_or(self.build_profile().split_debuginfo)
+    }
+
+    /// objcopy matching the compiler of a target, so cross-compiled executables are handled
+    /// by a tool that understands them
+    fn objcopy(&self, target: &TargetConfig) -> String {
+        if let Some(objcopy) = self.toolchain().and_then(|t| t.objcopy.as_ref()) {
+            return objcopy.clone();
+        }
+
+        let compiler = self.compiler(target);
+        match self.compiler_family(target) {
+            CompilerFamily::Clang => {
+                companion_tool(compiler, &["clang++", "clang"], "llvm-objcopy")
+            }
+            _ => without_version(&companion_tool(compiler, &["g++", "gcc"], "objcopy")),
+        }
+    }
+
+    /// Commands that strip a freshly linked executable and split off its debug info, run in
+    /// order after linking
+    pub fn post_link_commands(&self, target: &TargetConfig, output: &Path) -> Vec<Vec<String>> {
+        if target.target_type == TargetType::StaticLibrary {
+            return vec![];
+        }
+
+        let strip = self.strip_mode(target);
+        let split = self.split_debuginfo(target);
+        let strip_flag = match (strip, split) {
+            (StripMode::Symbols, _) => "--strip-all",
+            (StripMode::Debuginfo, _) | (StripMode::None, true) => "--strip-debug",
+            (StripMode::None, false) => return vec![],
+        };
+
+        let objcopy = self.objcopy(target);
+        let output = output.display().to_string();
+        let debug_file = debug_file_path(Path::new(&output)).display().to_string();
+
+        let mut commands = vec![];
+        if split {
+            commands.push(vec![
+                objcopy.clone(),
+                "--only-keep-debug".to_string(),
+                output.clone(),
+                debug_file.clone(),
+            ]);
+        }
+        commands.push(vec![
+            objcopy.clone(),
+            strip_flag.to_string(),
+            output.clone(),
+        ]);
+        if split {
+            commands.push(vec![
+                objcopy,
+                format!("--add-gnu-debuglink={}", debug_file),
+                output,
+            ]);
+        }
+        commands
+    }
+}
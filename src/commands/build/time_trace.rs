@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{commands::build::Builder, config::TargetConfig};
+
+/// Number of entries per category that are logged after a build
+const LOGGED_ENTRIES: usize = 5;
+
+/// Number of entries per category kept in the written report
+const REPORTED_ENTRIES: usize = 100;
+
+/// A Chrome trace file as written by clang's `-ftime-trace`
+#[derive(Debug, Deserialize)]
+struct TraceFile {
+    #[serde(rename = "traceEvents")]
+    trace_events: Vec<TraceEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TraceEvent {
+    name: String,
+
+    /// Duration in microseconds
+    #[serde(default)]
+    dur: Option<f64>,
+
+    #[serde(default)]
+    args: Option<TraceArgs>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TraceArgs {
+    #[serde(default)]
+    detail: Option<String>,
+}
+
+/// Aggregated cost of a single include, template or function across a target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceItem {
+    pub name: String,
+
+    /// Total time spent across all translation units, in milliseconds
+    pub total_ms: f64,
+
+    /// Number of times it showed up across all translation units
+    pub count: usize,
+}
+
+/// Most expensive includes, template instantiations and functions of a target
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimeTraceReport {
+    /// Number of traces that were aggregated
+    pub traces: usize,
+
+    pub includes: Vec<TraceItem>,
+    pub templates: Vec<TraceItem>,
+    pub functions: Vec<TraceItem>,
+}
+
+#[derive(Default)]
+struct Aggregate {
+    items: HashMap<String, TraceItem>,
+}
+
+impl Aggregate {
+    fn add(&mut self, name: &str, dur_us: f64) {
+        let item = self
+            .items
+            .entry(name.to_string())
+            .or_insert_with(|| TraceItem {
+                name: name.to_string(),
+                total_ms: 0.0,
+                count: 0,
+            });
+        item.total_ms += dur_us / 1000.0;
+        item.count += 1;
+    }
+
+    fn into_sorted(self) -> Vec<TraceItem> {
+        let mut items = self.items.into_values().collect::<Vec<_>>();
+        items.sort_by(|a, b| b.total_ms.total_cmp(&a.total_ms));
+        items.truncate(REPORTED_ENTRIES);
+        items
+    }
+}
+
+/// Path of the trace clang writes for `obj` (the object path with a `.json` extension)
+pub fn trace_path(obj: &Path) -> PathBuf {
+    obj.with_extension("json")
+}
+
+impl TimeTraceReport {
+    /// Aggregate the given trace files into a single report
+    pub fn aggregate(traces: &[PathBuf]) -> Result<Self> {
+        let mut includes = Aggregate::default();
+        let mut templates = Aggregate::default();
+        let mut functions = Aggregate::default();
+
+        for trace in traces {
+            let data = std::fs::read_to_string(trace)?;
+            let trace: TraceFile = serde_json::from_str(&data)?;
+
+            for event in trace.trace_events {
+                let (Some(dur), Some(detail)) =
+                    (event.dur, event.args.and_then(|args| args.detail))
+                else {
+                    continue;
+                };
+
+                match event.name.as_str() {
+                    "Source" => includes.add(&detail, dur),
+                    "InstantiateClass" | "InstantiateFunction" => templates.add(&detail, dur),
+                    "OptFunction" | "CodeGen Function" => functions.add(&detail, dur),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Self {
+            traces: traces.len(),
+            includes: includes.into_sorted(),
+            templates: templates.into_sorted(),
+            functions: functions.into_sorted(),
+        })
+    }
+
+    fn log_summary(&self, target: &str) {
+        for (category, items) in [
+            ("includes", &self.includes),
+            ("template instantiations", &self.templates),
+            ("functions", &self.functions),
+        ] {
+            if items.is_empty() {
+                continue;
+            }
+            log::info!("Most expensive {} in `{}`:", category, target);
+            for item in items.iter().take(LOGGED_ENTRIES) {
+                log::info!(
+                    "  {:>9.1}ms  {:>4}x  {}",
+                    item.total_ms,
+                    item.count,
+                    item.name
+                );
+            }
+        }
+    }
+}
+
+impl<'a> Builder<'a> {
+    /// Aggregate the `-ftime-trace` output of every object file of a target into a report
+    pub fn aggregate_time_traces(
+        &self,
+        target: &TargetConfig,
+        obj_files: &[PathBuf],
+    ) -> Result<()> {
+        let traces = obj_files
+            .iter()
+            .map(|obj| trace_path(obj))
+            .filter(|trace| trace.is_file())
+            .collect::<Vec<_>>();
+
+        if traces.is_empty() {
+            log::warn!("No time traces were produced for `{}`", target.name);
+            return Ok(());
+        }
+
+        let report = TimeTraceReport::aggregate(&traces)?;
+        report.log_summary(&target.name);
+
+        let report_path = self.target_out_dir(target).join("time-trace-report.json");
+        self.sh
+            .write_file(&report_path, serde_json::to_string_pretty(&report)?)?;
+        log::info!("Wrote time trace report to {}", report_path.display());
+
+        Ok(())
+    }
+}
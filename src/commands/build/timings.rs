@@ -0,0 +1,233 @@
+use std::{
+    fmt,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Number of steps listed in the slowest-steps summary
+const SUMMARY_LEN: usize = 10;
+
+/// Kind of build step being timed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepKind {
+    /// Configuring a CMake dependency
+    Configure,
+
+    /// Building a CMake dependency
+    Dependency,
+
+    /// Running a custom build step of a target
+    Generate,
+
+    /// Compiling a single translation unit
+    Compile,
+
+    /// Linking or archiving a target
+    Link,
+}
+
+impl fmt::Display for StepKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // use `pad` so the kind lines up in the summary table
+        f.pad(match self {
+            StepKind::Configure => "configure",
+            StepKind::Dependency => "dependency",
+            StepKind::Generate => "generate",
+            StepKind::Compile => "compile",
+            StepKind::Link => "link",
+        })
+    }
+}
+
+/// A single timed build step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimingEntry {
+    pub kind: StepKind,
+
+    /// Target or dependency the step belongs to
+    pub target: String,
+
+    /// File (or dependency name) produced by the step
+    pub unit: String,
+
+    /// Seconds since the start of the build
+    pub start: f64,
+
+    /// Duration of the step in seconds
+    pub duration: f64,
+}
+
+/// Timing report written to the build directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimingReport {
+    /// Total wall-clock time of the build in seconds
+    pub total: f64,
+
+    /// Every timed step, in the order they finished
+    pub steps: Vec<TimingEntry>,
+}
+
+/// Records how long each step of a build takes
+pub struct Timings {
+    start: Instant,
+    entries: Mutex<Vec<TimingEntry>>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Run `f`, recording how long it took as a step of the given kind
+    pub fn time<T>(
+        &self,
+        kind: StepKind,
+        target: &str,
+        unit: impl fmt::Display,
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let started = Instant::now();
+        let result = f()?;
+        let entry = TimingEntry {
+            kind,
+            target: target.to_string(),
+            unit: unit.to_string(),
+            start: (started - self.start).as_secs_f64(),
+            duration: started.elapsed().as_secs_f64(),
+        };
+        self.entries.lock().unwrap().push(entry);
+        Ok(result)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn report(&self) -> TimingReport {
+        TimingReport {
+            total: self.elapsed().as_secs_f64(),
+            steps: self.entries.lock().unwrap().clone(),
+        }
+    }
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimingReport {
+    /// Log a table of the slowest steps of the build
+    pub fn log_summary(&self) {
+        let mut steps = self.steps.iter().collect::<Vec<_>>();
+        steps.sort_by(|a, b| b.duration.total_cmp(&a.duration));
+
+        log::info!(
+            "Build finished in {:.2}s ({} steps timed)",
+            self.total,
+            self.steps.len()
+        );
+        log::info!("Slowest steps:");
+        log::info!(
+            "  {:>8}  {:>6}  {:<10}  {:<16}  unit",
+            "time",
+            "share",
+            "kind",
+            "target"
+        );
+        for step in steps.into_iter().take(SUMMARY_LEN) {
+            let share = if self.total > 0.0 {
+                step.duration / self.total * 100.0
+            } else {
+                0.0
+            };
+            log::info!(
+                "  {:>7.2}s  {:>5.1}%  {:<10}  {:<16}  {}",
+                step.duration,
+                share,
+                step.kind,
+                step.target,
+                step.unit
+            );
+        }
+    }
+
+    /// Write the report as JSON and as an HTML timeline into `build_dir`
+    pub fn write(&self, build_dir: &Path) -> Result<()> {
+        let json_path = build_dir.join("jfb-timings.json");
+        std::fs::write(&json_path, serde_json::to_string_pretty(self)?)?;
+        log::info!("Wrote timings to {}", json_path.display());
+
+        let html_path = build_dir.join("jfb-timings.html");
+        std::fs::write(&html_path, self.to_html())?;
+        log::info!("Wrote timing report to {}", html_path.display());
+
+        Ok(())
+    }
+
+    fn to_html(&self) -> String {
+        let total = self.total.max(f64::EPSILON);
+
+        let mut rows = String::new();
+        for step in &self.steps {
+            let left = step.start / total * 100.0;
+            let width = (step.duration / total * 100.0).max(0.1);
+            rows.push_str(&format!(
+                r#"<tr><td>{kind}</td><td>{target}</td><td>{unit}</td><td class="num">{duration:.2}s</td><td class="timeline"><div class="bar {kind}" style="left: {left:.3}%; width: {width:.3}%"></div></td></tr>
+"#,
+                kind = step.kind,
+                target = escape_html(&step.target),
+                unit = escape_html(&step.unit),
+                duration = step.duration,
+            ));
+        }
+
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>jfb build timings</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; width: 100%; }}
+td, th {{ padding: 2px 8px; text-align: left; white-space: nowrap; }}
+td.num {{ text-align: right; }}
+td.timeline {{ position: relative; width: 50%; }}
+.bar {{ position: absolute; top: 3px; bottom: 3px; min-width: 1px; }}
+.configure {{ background: #9b59b6; }}
+.dependency {{ background: #8e44ad; }}
+.generate {{ background: #16a085; }}
+.compile {{ background: #3498db; }}
+.link {{ background: #e67e22; }}
+</style>
+</head>
+<body>
+<h1>jfb build timings</h1>
+<p>Total build time: {total:.2}s, {count} steps</p>
+<table>
+<tr><th>Kind</th><th>Target</th><th>Unit</th><th>Time</th><th>Timeline</th></tr>
+{rows}</table>
+</body>
+</html>
+"#,
+            total = self.total,
+            count = self.steps.len(),
+        )
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
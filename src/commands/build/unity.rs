@@ -0,0 +1,90 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Result;
+
+use crate::{
+    commands::build::{Builder, modules::is_module_source},
+    config::{TargetConfig, TargetLanguage},
+};
+
+/// A generated translation unit of a unity build
+#[derive(Debug, Clone)]
+pub struct UnityUnit {
+    /// Generated source that includes each of the members
+    pub source: PathBuf,
+
+    /// Object file the generated source compiles to
+    pub object: PathBuf,
+
+    /// Original sources combined into this unit
+    pub members: Vec<PathBuf>,
+}
+
+impl UnityUnit {
+    fn contents(&self) -> String {
+        self.members
+            .iter()
+            .map(|member| format!("#include \"{}\"\n", member.display()))
+            .collect()
+    }
+}
+
+impl<'a> Builder<'a> {
+    /// Split the sources of a target into the translation units of a unity build
+    pub fn unity_units(&self, target: &TargetConfig) -> Result<Vec<UnityUnit>> {
+        let (src_files, _) = self.discovered_sources(target)?;
+        if src_files.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // module interfaces are translation units of their own and can't be included
+        if let Some(src) = src_files.iter().find(|src| is_module_source(src)) {
+            return Err(anyhow::anyhow!(
+                "Target `{}` can't use a unity build with module source {}",
+                target.name,
+                src.display()
+            ));
+        }
+
+        let batch_size = match target.unity_batch_size {
+            0 => src_files.len(),
+            size => size,
+        };
+        let extension = match target.language {
+            TargetLanguage::C => "c",
+            TargetLanguage::Cpp => "cpp",
+        };
+        let unity_dir = self.target_internal_dir(target).join("unity");
+
+        Ok(src_files
+            .chunks(batch_size)
+            .enumerate()
+            .map(|(index, members)| UnityUnit {
+                source: unity_dir.join(format!("unity_{}.{}", index, extension)),
+                object: unity_dir.join(format!("unity_{}.o", index)),
+                members: members.to_vec(),
+            })
+            .collect())
+    }
+
+    /// Write the generated sources of a target's unity build, returning the members of each.
+    /// Sources are only rewritten when their members change, so unchanged units aren't rebuilt.
+    pub fn write_unity_sources(
+        &self,
+        target: &TargetConfig,
+    ) -> Result<HashMap<PathBuf, Vec<PathBuf>>> {
+        if !target.unity_build {
+            return Ok(HashMap::new());
+        }
+
+        let mut members = HashMap::new();
+        for unit in self.unity_units(target)? {
+            let contents = unit.contents();
+            if std::fs::read_to_string(&unit.source).ok().as_deref() != Some(&contents) {
+                self.sh.write_file(&unit.source, contents)?;
+            }
+            members.insert(unit.source, unit.members);
+        }
+        Ok(members)
+    }
+}
@@ -11,10 +11,9 @@ pub struct CleanOpts {
 }
 
 pub fn clean(args: &Args, opts: &CleanOpts) -> anyhow::Result<()> {
+    let cwd = std::env::current_dir()?;
     let config_path = &args.opts.config;
-    let base_dir = config_path
-        .parent()
-        .unwrap_or_else(|| std::path::Path::new("."));
+    let base_dir = config_path.parent().map(|p| cwd.join(p)).unwrap_or(cwd);
 
     let config = Config::load(config_path)?;
     let base_dir = base_dir.canonicalize()?;
@@ -0,0 +1,69 @@
+use anyhow::Result;
+use clap::Parser;
+
+use crate::{
+    commands::{
+        build::{BuildOpts, Builder},
+        test::{check_test_results, run_tests},
+    },
+    config::{Args, Config, CoverageMode},
+};
+
+#[derive(Debug, Clone, Parser)]
+pub struct CoverageOpts {
+    /// Build profile with coverage instrumentation (`coverage = "gcov"` or `"source"`)
+    #[arg(short, long, default_value = "coverage")]
+    pub profile: String,
+
+    /// Toolchain to build with, overriding the one selected by the profile
+    #[arg(short, long)]
+    pub toolchain: Option<String>,
+}
+
+impl CoverageOpts {
+    fn build_opts(&self) -> BuildOpts {
+        BuildOpts {
+            profile: self.profile.clone(),
+            toolchain: self.toolchain.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+pub fn coverage(args: &Args, opts: &CoverageOpts) -> Result<()> {
+    let build_opts = opts.build_opts();
+
+    let cwd = std::env::current_dir()?;
+    let config_path = &args.opts.config;
+    let base_dir = config_path.parent().map(|p| cwd.join(p)).unwrap_or(cwd);
+
+    let base_dir = base_dir.canonicalize()?;
+    let config = Config::load(config_path)?;
+    let builder = Builder::new(args, &config, &build_opts, &base_dir)?;
+
+    // make sure we'll be able to read the coverage data before building anything
+    if builder.build_profile().coverage == CoverageMode::Off {
+        return Err(anyhow::anyhow!(
+            "Profile `{}` doesn't enable coverage (set `coverage = \"gcov\"` or `coverage = \"source\"`)",
+            opts.profile
+        ));
+    }
+    builder.check_coverage_support()?;
+
+    crate::commands::build::build(args, &build_opts)?;
+
+    builder.reset_coverage()?;
+    let failed = run_tests(&builder, &config, &base_dir, &builder.coverage_env())?;
+
+    let report = builder.collect_coverage()?;
+    report.log_summary();
+
+    let report_dir = builder.coverage_dir();
+    report.write(&report_dir, &base_dir)?;
+    log::info!(
+        "Wrote coverage report to {}",
+        report_dir.join("index.html").display()
+    );
+
+    check_test_results(&failed)
+}
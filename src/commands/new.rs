@@ -204,10 +204,10 @@ pub fn new(opts: &NewOpts) -> Result<()> {
                 let _guard = sh.push_dir(&target.name);
 
                 match (&target.target_type, &target.language) {
-                    (TargetType::Binary, TargetLanguage::C) => {
+                    (TargetType::Binary | TargetType::Test, TargetLanguage::C) => {
                         sh.write_file("src/main.c", template_c_executable_main!())?;
                     }
-                    (TargetType::Binary, TargetLanguage::Cpp) => {
+                    (TargetType::Binary | TargetType::Test, TargetLanguage::Cpp) => {
                         sh.write_file("src/main.cpp", template_cpp_executable_main!())?;
                     }
                     (TargetType::StaticLibrary, TargetLanguage::C) => {
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use clap::Parser;
+use xshell::{Shell, cmd};
+
+use crate::{
+    commands::build::{BuildOpts, Builder, pgo::PgoPhase},
+    config::{Args, Config, TargetType},
+};
+
+#[derive(Debug, Clone, Parser)]
+pub struct PgoOpts {
+    /// Build profile to optimize
+    #[arg(short, long, default_value = "release")]
+    pub profile: String,
+
+    /// Toolchain to build with, overriding the one selected by the profile
+    #[arg(short, long)]
+    pub toolchain: Option<String>,
+
+    /// Arguments to train the instrumented executable with, when `[pgo] train` isn't set
+    #[arg(last = true)]
+    pub args: Vec<String>,
+}
+
+impl PgoOpts {
+    fn build_opts(&self, phase: PgoPhase) -> BuildOpts {
+        BuildOpts {
+            profile: self.profile.clone(),
+            toolchain: self.toolchain.clone(),
+            pgo: Some(phase),
+            ..Default::default()
+        }
+    }
+}
+
+pub fn pgo(args: &Args, opts: &PgoOpts) -> Result<()> {
+    let instrumented_opts = opts.build_opts(PgoPhase::Generate);
+    let optimized_opts = opts.build_opts(PgoPhase::Use);
+
+    let cwd = std::env::current_dir()?;
+    let config_path = &args.opts.config;
+    let base_dir = config_path.parent().map(|p| cwd.join(p)).unwrap_or(cwd);
+
+    let base_dir = base_dir.canonicalize()?;
+    let config = Config::load(config_path)?;
+    let instrumented = Builder::new(args, &config, &instrumented_opts, &base_dir)?;
+    let optimized = Builder::new(args, &config, &optimized_opts, &base_dir)?;
+    instrumented.check_pgo_support()?;
+
+    let executable = config
+        .targets
+        .iter()
+        .find(|t| matches!(t.target_type, TargetType::Binary) && !t.host_tool)
+        .ok_or_else(|| anyhow::anyhow!("No executable target found in configuration"))?;
+
+    log::info!("Building instrumented `{}`", opts.profile);
+    crate::commands::build::build(args, &instrumented_opts)?;
+    instrumented.reset_pgo_data()?;
+
+    let exe_path = instrumented.target_output(executable);
+    let sh = Shell::new()?;
+    let _guard = sh.push_dir(&base_dir);
+    let train = config
+        .pgo
+        .train
+        .iter()
+        .map(|arg| arg.replace("{binary}", &exe_path.display().to_string()))
+        .collect::<Vec<_>>();
+    match train.split_first() {
+        Some((program, train_args)) => {
+            log::info!("Training with: {}", train.join(" "));
+            cmd!(sh, "{program}").args(train_args).quiet().run()?;
+        }
+        None => {
+            log::info!("Training with: {}", exe_path.display());
+            instrumented
+                .run_command(&sh, &exe_path)
+                .args(&opts.args)
+                .quiet()
+                .run()?;
+        }
+    }
+
+    // start the optimized build from scratch, since it depends on the profile data as much as
+    // on the sources
+    sh.remove_path(optimized.profile_dir())?;
+    optimized.import_pgo_data(&instrumented)?;
+
+    log::info!("Building optimized `{}`", opts.profile);
+    crate::commands::build::build(args, &optimized_opts)?;
+
+    log::info!(
+        "Optimized executable: {}",
+        optimized.target_output(executable).display()
+    );
+
+    Ok(())
+}
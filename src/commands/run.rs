@@ -1,6 +1,9 @@
-use crate::config::{Args, Config, TargetType};
+use crate::{
+    commands::build::Builder,
+    config::{Args, Config, TargetType},
+};
 use anyhow::Result;
-use xshell::{Shell, cmd};
+use xshell::Shell;
 
 use super::build::BuildOpts;
 
@@ -14,17 +17,15 @@ pub fn run(args: &Args, build_opts: &BuildOpts) -> Result<()> {
 
     let base_dir = base_dir.canonicalize()?;
     let config = Config::load(config_path)?;
+    let builder = Builder::new(args, &config, build_opts, &base_dir)?;
 
-    let build_dir = base_dir
-        .join(&config.workspace.build_dir)
-        .join(&build_opts.profile);
     let executable = config
         .targets
         .iter()
-        .find(|t| matches!(t.target_type, TargetType::Binary))
+        .find(|t| matches!(t.target_type, TargetType::Binary) && !t.host_tool)
         .ok_or_else(|| anyhow::anyhow!("No executable target found in configuration"))?;
 
-    let exe_path = build_dir.join(&executable.name).join(&executable.name);
+    let exe_path = builder.target_output(executable);
     if !exe_path.exists() {
         return Err(anyhow::anyhow!(
             "Executable not found: {}",
@@ -35,7 +36,12 @@ pub fn run(args: &Args, build_opts: &BuildOpts) -> Result<()> {
     log::info!("Running executable: {}", exe_path.display());
     let sh = Shell::new()?;
     let _guard = sh.push_dir(&base_dir);
-    cmd!(sh, "{exe_path}").quiet().run()?;
+    for (var, value) in builder.sanitizer_env() {
+        log::debug!("Setting {}={}", var, value);
+        sh.set_var(var, value);
+    }
+
+    builder.run_command(&sh, &exe_path).quiet().run()?;
 
     Ok(())
 }
@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use anyhow::Result;
+use xshell::Shell;
+
+use crate::{
+    commands::build::{BuildOpts, Builder},
+    config::{Args, Config, TargetType},
+};
+
+pub fn test(args: &Args, build_opts: &BuildOpts) -> Result<()> {
+    // build first
+    crate::commands::build::build(args, build_opts)?;
+
+    let cwd = std::env::current_dir()?;
+    let config_path = &args.opts.config;
+    let base_dir = config_path.parent().map(|p| cwd.join(p)).unwrap_or(cwd);
+
+    let base_dir = base_dir.canonicalize()?;
+    let config = Config::load(config_path)?;
+    let builder = Builder::new(args, &config, build_opts, &base_dir)?;
+
+    let failed = run_tests(&builder, &config, &base_dir, &[])?;
+    check_test_results(&failed)
+}
+
+/// Run every test target from the project directory, returning the names of the ones that failed
+pub fn run_tests(
+    builder: &Builder,
+    config: &Config,
+    base_dir: &Path,
+    env: &[(&str, String)],
+) -> Result<Vec<String>> {
+    let tests = config
+        .targets
+        .iter()
+        .filter(|t| t.target_type == TargetType::Test)
+        .collect::<Vec<_>>();
+    if tests.is_empty() {
+        log::warn!("No test targets found in configuration");
+    }
+
+    let sh = Shell::new()?;
+    let _guard = sh.push_dir(base_dir);
+    for (var, value) in builder.sanitizer_env() {
+        log::debug!("Setting {}={}", var, value);
+        sh.set_var(var, value);
+    }
+    for (var, value) in env {
+        log::debug!("Setting {}={}", var, value);
+        sh.set_var(var, value);
+    }
+
+    let mut failed = vec![];
+    for test in tests {
+        let exe_path = builder.target_output(test);
+        log::info!("Running test: {}", test.name);
+
+        // a failing test shouldn't stop the others from running
+        let status = std::process::Command::from(builder.run_command(&sh, &exe_path)).status()?;
+        if status.success() {
+            log::info!("Test `{}` passed", test.name);
+        } else {
+            log::error!("Test `{}` failed ({})", test.name, status);
+            failed.push(test.name.clone());
+        }
+    }
+
+    Ok(failed)
+}
+
+/// Turn the names of failed tests into an error
+pub fn check_test_results(failed: &[String]) -> Result<()> {
+    if failed.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow::anyhow!(
+        "{} test(s) failed: {}",
+        failed.len(),
+        failed.join(", ")
+    ))
+}
@@ -0,0 +1,26 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::{
+    commands::build::{BuildOpts, Builder},
+    config::{Args, Config},
+};
+
+pub fn why(args: &Args, file: &Path, build_opts: &BuildOpts) -> Result<()> {
+    let cwd = std::env::current_dir()?;
+    let base_dir = args
+        .opts
+        .config
+        .parent()
+        .map(|p| cwd.join(p))
+        .unwrap_or_else(|| cwd.clone());
+    let base_dir = base_dir.canonicalize()?;
+
+    let config = Config::load(&args.opts.config)?;
+
+    // object files and artifacts may not exist yet, so fall back to a plain absolute path
+    let file = file.canonicalize().unwrap_or_else(|_| cwd.join(file));
+
+    Builder::new(args, &config, build_opts, &base_dir)?.why(&file)
+}
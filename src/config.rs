@@ -4,9 +4,11 @@ use std::{
 };
 
 use clap::{Parser, Subcommand, ValueEnum};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::commands::{build::BuildOpts, clean::CleanOpts, new::NewOpts};
+use crate::commands::{
+    build::BuildOpts, clean::CleanOpts, coverage::CoverageOpts, new::NewOpts, pgo::PgoOpts,
+};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
@@ -52,6 +54,34 @@ pub enum Command {
         #[clap(flatten)]
         opts: CleanOpts,
     },
+
+    /// Build and run the test targets
+    Test {
+        #[clap(flatten)]
+        build_opts: BuildOpts,
+    },
+
+    /// Run the test targets with coverage instrumentation and write a coverage report
+    Coverage {
+        #[clap(flatten)]
+        opts: CoverageOpts,
+    },
+
+    /// Build with profile-guided optimization: build an instrumented binary, train it, then
+    /// rebuild using the collected profile
+    Pgo {
+        #[clap(flatten)]
+        opts: PgoOpts,
+    },
+
+    /// Explain why a source file, object file or artifact would be rebuilt
+    Why {
+        /// File to explain
+        file: PathBuf,
+
+        #[clap(flatten)]
+        build_opts: BuildOpts,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,10 +90,18 @@ pub struct Config {
     #[serde(default)]
     pub workspace: WorkspaceConfig,
 
-    /// Global build configuration
+    /// Global build configuration. A profile can start from another with `inherits = "<name>"`,
+    /// and splice the parent's list fields into its own with `"..."`.
     #[serde(rename = "profile")]
+    #[serde(default = "BuildConfig::default_profiles")]
+    #[serde(deserialize_with = "deserialize_profiles")]
     pub build_profiles: HashMap<String, BuildConfig>,
 
+    /// Toolchain definitions, selectable per profile or with `--toolchain`
+    #[serde(rename = "toolchain")]
+    #[serde(default)]
+    pub toolchains: HashMap<String, ToolchainConfig>,
+
     /// Dependency configurations
     #[serde(default)]
     pub dependencies: HashMap<String, DependencyConfig>,
@@ -72,6 +110,18 @@ pub struct Config {
     #[serde(rename = "target")]
     #[serde(default)]
     pub targets: Vec<TargetConfig>,
+
+    /// Object cache configuration
+    #[serde(default)]
+    pub cache: CacheConfig,
+
+    /// Profile-guided optimization configuration
+    #[serde(default)]
+    pub pgo: PgoConfig,
+
+    /// Feature checks run against the active toolchain, with the results in a generated `config.h`
+    #[serde(default)]
+    pub checks: ChecksConfig,
 }
 
 impl Config {
@@ -83,21 +133,89 @@ impl Config {
                 ..Default::default()
             },
             build_profiles: BuildConfig::default_profiles(),
+            toolchains: HashMap::new(),
             dependencies: HashMap::new(),
             targets: Vec::new(),
+            cache: CacheConfig::default(),
+            pgo: PgoConfig::default(),
+            checks: ChecksConfig::default(),
         }
     }
 
-    /// Load configuration from a TOML file
+    /// Load configuration from a TOML file, with the `[cache]` settings of the user
+    /// configuration (if any) taking precedence over the project's
     pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let config = config::Config::builder()
-            .add_source(config::File::from(path.as_ref()).required(true))
-            .build()?;
+        let mut builder =
+            config::Config::builder().add_source(config::File::from(path.as_ref()).required(true));
+
+        // only the machine-specific cache settings are taken from the user configuration, so a
+        // developer machine stays read-only whatever the project sets
+        if let Some(user_config) = Self::user_config_path() {
+            let user_config = config::Config::builder()
+                .add_source(config::File::from(user_config).required(false))
+                .build()?;
+            if let Ok(cache) = user_config.get_table("cache") {
+                for (key, value) in cache {
+                    builder = builder.set_override(format!("cache.{}", key), value)?;
+                }
+            }
+        }
 
-        let config = config.try_deserialize::<Config>()?;
+        let config = builder.build()?.try_deserialize::<Config>()?;
 
         Ok(config)
     }
+
+    /// Look up a build profile by name, with a helpful error if it doesn't exist
+    pub fn build_profile(&self, name: &str) -> anyhow::Result<&BuildConfig> {
+        self.build_profiles
+            .get(name)
+            .ok_or_else(|| unknown_name_error("profile", name, self.build_profiles.keys()))
+    }
+
+    /// Look up a toolchain by name, with a helpful error if it doesn't exist
+    pub fn toolchain(&self, name: &str) -> anyhow::Result<&ToolchainConfig> {
+        self.toolchains
+            .get(name)
+            .ok_or_else(|| unknown_name_error("toolchain", name, self.toolchains.keys()))
+    }
+
+    /// Path of the per-user configuration file (`~/.config/jfb/config.toml`), holding the
+    /// machine-specific `[cache]` settings
+    pub fn user_config_path() -> Option<PathBuf> {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_home.join("jfb").join("config.toml"))
+    }
+}
+
+/// Build an error for a profile, toolchain, etc. that isn't defined, listing the ones that are
+/// and suggesting the closest match
+fn unknown_name_error<'a>(
+    kind: &str,
+    name: &str,
+    available: impl Iterator<Item = &'a String>,
+) -> anyhow::Error {
+    let mut available = available.map(String::as_str).collect::<Vec<_>>();
+    available.sort();
+
+    let mut message = format!("Unknown {} `{}`", kind, name);
+    let suggestion = available
+        .iter()
+        .map(|candidate| (candidate, strsim::jaro_winkler(name, candidate)))
+        .filter(|(_, similarity)| *similarity > 0.8)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b));
+    if let Some((suggestion, _)) = suggestion {
+        message.push_str(&format!(", did you mean `{}`?", suggestion));
+    }
+    if available.is_empty() {
+        message.push_str(&format!(" (no {}s are defined)", kind));
+    } else {
+        message.push_str(&format!(" (available {}s: {})", kind, available.join(", ")));
+    }
+
+    anyhow::anyhow!(message)
 }
 
 /// Workspace configuration
@@ -107,6 +225,9 @@ pub struct WorkspaceConfig {
     /// Name of the project
     pub name: String,
 
+    /// Version of the project, available to targets through `jfb_build_info.h`
+    pub version: String,
+
     /// Directory to place build artifacts
     pub build_dir: PathBuf,
 
@@ -118,12 +239,106 @@ impl Default for WorkspaceConfig {
     fn default() -> Self {
         Self {
             name: "myproject".to_string(),
+            version: "0.1.0".to_string(),
             build_dir: PathBuf::from("build"),
             dep_dir: PathBuf::from("deps"),
         }
     }
 }
 
+/// Content-addressed object cache shared across projects and profiles
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Reuse object files from the cache instead of recompiling them
+    pub enabled: bool,
+
+    /// Directory of the local object cache (defaults to `~/.cache/jfb/objects`)
+    pub dir: Option<PathBuf>,
+
+    /// URL of a remote object cache, read with HTTP GET and written with HTTP PUT (`file://`
+    /// URLs work too)
+    pub remote: Option<String>,
+
+    /// Only read from the remote cache, never upload to it
+    pub read_only: bool,
+}
+
+impl CacheConfig {
+    /// Directory of the local object cache, if one could be determined
+    pub fn local_dir(&self) -> Option<PathBuf> {
+        if let Some(dir) = &self.dir {
+            return Some(dir.clone());
+        }
+
+        let cache_home = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(cache_home.join("jfb").join("objects"))
+    }
+}
+
+/// Profile-guided optimization settings for `jfb pgo`
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct PgoConfig {
+    /// Command that trains the instrumented build, run from the project directory with
+    /// `{binary}` replaced by the instrumented executable. Defaults to running the executable
+    /// with the arguments given to `jfb pgo`.
+    pub train: Vec<String>,
+}
+
+/// Autoconf-style feature checks. Results are defined in a generated `config.h`, which every
+/// target can include (`HAVE_CONFIG_H` is defined when it exists).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChecksConfig {
+    /// Headers to look for, defining `HAVE_<HEADER>` (e.g. `sys/epoll.h` -> `HAVE_SYS_EPOLL_H`)
+    pub headers: Vec<String>,
+
+    /// Functions to look for by linking against them, defining `HAVE_<FUNCTION>`
+    pub functions: Vec<String>,
+
+    /// Symbols to look for a declaration of in `includes`, defining `HAVE_DECL_<SYMBOL>` to 1 or 0
+    pub decls: Vec<String>,
+
+    /// Types to measure, defining `SIZEOF_<TYPE>` (e.g. `long long` -> `SIZEOF_LONG_LONG`), or 0
+    /// if the type doesn't exist
+    pub sizes: Vec<String>,
+
+    /// Compiler flags to add to every target when the compiler supports them
+    pub flags: Vec<String>,
+
+    /// Headers included by the declaration and size checks
+    pub includes: Vec<String>,
+}
+
+impl Default for ChecksConfig {
+    fn default() -> Self {
+        Self {
+            headers: vec![],
+            functions: vec![],
+            decls: vec![],
+            sizes: vec![],
+            flags: vec![],
+            includes: ["stddef.h", "stdint.h", "stdio.h", "stdlib.h", "string.h"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+impl ChecksConfig {
+    /// Whether there's anything to check
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+            && self.functions.is_empty()
+            && self.decls.is_empty()
+            && self.sizes.is_empty()
+            && self.flags.is_empty()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct BuildConfig {
@@ -160,11 +375,40 @@ pub struct BuildConfig {
     /// Additional compiler flags
     pub flags: Vec<String>,
 
+    /// Additional linker flags
+    pub link_flags: Vec<String>,
+
+    /// Sanitizers to build with (address, undefined, thread, memory, leak)
+    pub sanitizers: Vec<String>,
+
+    /// Coverage instrumentation to build with (off, gcov, source)
+    pub coverage: CoverageMode,
+
+    /// Link-time optimization (off, thin, full)
+    pub lto: LtoMode,
+
+    /// What to strip from linked executables (none, debuginfo, symbols)
+    pub strip: StripMode,
+
+    /// Move the debug info of linked executables into a separate `<name>.debug` file, linked
+    /// back with a `.gnu_debuglink` section
+    pub split_debuginfo: bool,
+
+    /// Produce the same artifacts regardless of where and when the project is built
+    pub reproducible: bool,
+
     /// Preprocessor defines
     pub defines: Vec<String>,
 
     /// Extra CMake flags to use when building dependencies under this profile
     pub cmake_flags: Vec<String>,
+
+    /// Toolchain to build with (see `[toolchain.<name>]`), unless overridden by `--toolchain`
+    pub toolchain: Option<String>,
+
+    /// Program to prefix every compile command with (e.g. `ccache` or `sccache`), also used for
+    /// dependencies built with CMake
+    pub compiler_launcher: Option<String>,
 }
 
 impl BuildConfig {
@@ -190,10 +434,170 @@ impl BuildConfig {
                 ..Default::default()
             },
         );
+        profiles.insert(
+            "coverage".to_string(),
+            BuildConfig {
+                opt_level: "0".to_string(),
+                debug: true,
+                warnings_as_errors: false,
+                coverage: CoverageMode::Gcov,
+                cmake_flags: vec!["-DCMAKE_BUILD_TYPE=Debug".to_string()],
+                ..Default::default()
+            },
+        );
         profiles
     }
 }
 
+/// Kind of coverage instrumentation to build with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverageMode {
+    /// No coverage instrumentation
+    #[default]
+    Off,
+
+    /// gcov instrumentation (`--coverage`), reported with GCC's `gcov`
+    Gcov,
+
+    /// Clang source-based coverage, reported with `llvm-profdata` and `llvm-cov`
+    Source,
+}
+
+/// Kind of link-time optimization to build with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LtoMode {
+    /// No link-time optimization
+    #[default]
+    Off,
+
+    /// Parallel, summary-based LTO (`-flto=thin` with Clang, `-flto=auto` with GCC)
+    Thin,
+
+    /// Whole-program LTO (`-flto`)
+    Full,
+}
+
+/// What to strip from linked executables
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StripMode {
+    /// Keep everything
+    #[default]
+    None,
+
+    /// Remove debug info, keeping the symbol table
+    Debuginfo,
+
+    /// Remove debug info and all symbols
+    Symbols,
+}
+
+/// Marker that splices the parent profile's list into a list field of an inheriting profile, e.g.
+/// `flags = ["...", "-fsanitize=address"]`
+pub const INHERIT_LIST_MARKER: &str = "...";
+
+type ProfileTable = config::Map<String, config::Value>;
+
+fn deserialize_profiles<'de, D>(deserializer: D) -> Result<HashMap<String, BuildConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let definitions = HashMap::<String, ProfileTable>::deserialize(deserializer)?;
+    let mut profiles =
+        resolve_profiles(&definitions).map_err(|e| serde::de::Error::custom(format!("{:#}", e)))?;
+
+    // the built-in profiles are always available, even if the config doesn't mention them
+    for (name, profile) in BuildConfig::default_profiles() {
+        profiles.entry(name).or_insert(profile);
+    }
+
+    Ok(profiles)
+}
+
+/// Resolve profile definitions as written in the configuration file, applying `inherits`
+pub fn resolve_profiles(
+    definitions: &HashMap<String, ProfileTable>,
+) -> anyhow::Result<HashMap<String, BuildConfig>> {
+    let mut resolved = HashMap::new();
+    for name in definitions.keys() {
+        resolve_profile(name, definitions, &mut resolved, &mut vec![])?;
+    }
+    Ok(resolved)
+}
+
+fn resolve_profile(
+    name: &str,
+    definitions: &HashMap<String, ProfileTable>,
+    resolved: &mut HashMap<String, BuildConfig>,
+    chain: &mut Vec<String>,
+) -> anyhow::Result<BuildConfig> {
+    if let Some(profile) = resolved.get(name) {
+        return Ok(profile.clone());
+    }
+    if chain.iter().any(|n| n == name) {
+        chain.push(name.to_string());
+        return Err(anyhow::anyhow!(
+            "Profile inheritance cycle: {}",
+            chain.join(" -> ")
+        ));
+    }
+
+    let Some(definition) = definitions.get(name) else {
+        // built-in profiles can be inherited from without being defined
+        return BuildConfig::default_profiles()
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("Profile `{}` is not defined", name));
+    };
+
+    let mut fields = definition.clone();
+    let parent = match fields.remove("inherits") {
+        Some(parent) => {
+            let parent = parent.into_string()?;
+            chain.push(name.to_string());
+            let parent = resolve_profile(&parent, definitions, resolved, chain)
+                .map_err(|e| e.context(format!("Failed to resolve profile `{}`", name)))?;
+            chain.pop();
+            parent
+        }
+        None => BuildConfig::default(),
+    };
+
+    // start from the parent, then apply whatever this profile sets
+    let mut merged = config::Config::try_from(&parent)?.try_deserialize::<ProfileTable>()?;
+    for (key, value) in fields {
+        let value = match (merged.remove(&key), value.clone().into_array()) {
+            (Some(parent_value), Ok(items))
+                if items
+                    .iter()
+                    .any(|item| item.to_string() == INHERIT_LIST_MARKER) =>
+            {
+                let parent_items = parent_value.into_array()?;
+                let items = items
+                    .into_iter()
+                    .flat_map(|item| {
+                        if item.to_string() == INHERIT_LIST_MARKER {
+                            parent_items.clone()
+                        } else {
+                            vec![item]
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                config::Value::from(items)
+            }
+            _ => value,
+        };
+        merged.insert(key, value);
+    }
+
+    let profile = config::Value::from(merged)
+        .try_deserialize::<BuildConfig>()
+        .map_err(|e| anyhow::anyhow!("Invalid profile `{}`: {}", name, e))?;
+    resolved.insert(name.to_string(), profile.clone());
+    Ok(profile)
+}
+
 impl Default for BuildConfig {
     fn default() -> Self {
         Self {
@@ -219,8 +623,17 @@ impl Default for BuildConfig {
                 "-fstack-protector-strong".to_string(),
                 "-Wno-unused-parameter".to_string(),
             ],
+            link_flags: vec![],
+            sanitizers: vec![],
+            coverage: CoverageMode::Off,
+            lto: LtoMode::Off,
+            strip: StripMode::None,
+            split_debuginfo: false,
+            reproducible: false,
             defines: vec![],
             cmake_flags: vec![],
+            toolchain: None,
+            compiler_launcher: None,
         }
     }
 }
@@ -264,6 +677,18 @@ pub struct BuildConfigOverrides {
     /// Additional compiler flags
     pub flags: Option<Vec<String>>,
 
+    /// Additional linker flags
+    pub link_flags: Option<Vec<String>>,
+
+    /// Link-time optimization (off, thin, full)
+    pub lto: Option<LtoMode>,
+
+    /// What to strip from the linked executable (none, debuginfo, symbols)
+    pub strip: Option<StripMode>,
+
+    /// Move the debug info of the linked executable into a separate `.debug` file
+    pub split_debuginfo: Option<bool>,
+
     /// Preprocessor defines
     pub defines: Option<Vec<String>>,
 
@@ -271,6 +696,48 @@ pub struct BuildConfigOverrides {
     pub cmake_flags: Option<Vec<String>>,
 }
 
+/// A compiler toolchain, e.g. for cross-compiling. Anything left unset falls back to the build
+/// profile.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ToolchainConfig {
+    /// C compiler to use
+    pub c_compiler: Option<String>,
+
+    /// C++ compiler to use
+    pub cpp_compiler: Option<String>,
+
+    /// C linker to use (defaults to the C compiler)
+    pub c_linker: Option<String>,
+
+    /// C++ linker to use (defaults to the C++ compiler)
+    pub cpp_linker: Option<String>,
+
+    /// Archiver to create static libraries with (defaults to `ar`)
+    pub archiver: Option<String>,
+
+    /// objcopy to strip and split debug info with (defaults to the one matching the compiler)
+    pub objcopy: Option<String>,
+
+    /// Sysroot to compile and link against
+    pub sysroot: Option<PathBuf>,
+
+    /// Target triple, passed as `--target` to compilers that accept it (e.g. clang)
+    pub target: Option<String>,
+
+    /// Additional compiler flags
+    pub flags: Vec<String>,
+
+    /// Additional linker flags
+    pub link_flags: Vec<String>,
+
+    /// CMake toolchain file to configure dependencies with
+    pub cmake_toolchain_file: Option<PathBuf>,
+
+    /// Command to run built executables through (e.g. `["qemu-aarch64", "-L", "/usr/aarch64-linux-gnu"]`)
+    pub runner: Vec<String>,
+}
+
 /// Target programming language
 #[derive(Copy, ValueEnum, Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
 pub enum TargetLanguage {
@@ -297,6 +764,10 @@ pub enum TargetType {
     /// Static library target
     #[serde(rename = "staticlib", alias = "lib")]
     StaticLibrary,
+
+    /// Test executable, run by `jfb test` and `jfb coverage`
+    #[serde(rename = "test")]
+    Test,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -306,7 +777,7 @@ pub struct TargetConfig {
     #[serde(rename = "name")]
     pub name: String,
 
-    /// Type of the target (executable, staticlib, sharedlib)
+    /// Type of the target (binary, staticlib, test)
     #[serde(rename = "type")]
     pub target_type: TargetType,
 
@@ -328,6 +799,27 @@ pub struct TargetConfig {
     /// External dependencies to link against
     pub dependencies: Vec<String>,
 
+    /// Header to precompile and include in every source file of the target
+    pub precompiled_header: Option<PathBuf>,
+
+    /// Compile the sources in batches combined into single translation units. The sources of
+    /// a batch must not clash on names with internal linkage.
+    pub unity_build: bool,
+
+    /// Number of sources combined into each unity translation unit (0 combines all of them)
+    pub unity_batch_size: usize,
+
+    /// Always build this target with the host toolchain, even when cross-compiling, so it can
+    /// run as part of the build (see `{tool:<name>}` in generate steps)
+    pub host_tool: bool,
+
+    /// Files to embed as byte arrays, as glob patterns relative to the project directory (e.g.
+    /// `assets/*.bin`). Each one is declared in a generated `resources.h`.
+    pub resources: Vec<String>,
+
+    /// Custom build steps that generate files for this target before it's compiled
+    pub generate: Vec<GenerateRule>,
+
     /// Build configuration overrides for this target
     #[serde(rename = "build")]
     pub build_overrides: Option<BuildConfigOverrides>,
@@ -344,11 +836,34 @@ impl Default for TargetConfig {
             library_dirs: vec![],
             libraries: vec![],
             dependencies: vec![],
+            precompiled_header: None,
+            unity_build: false,
+            unity_batch_size: 8,
+            host_tool: false,
+            resources: vec![],
+            generate: vec![],
             build_overrides: None,
         }
     }
 }
 
+/// A custom build step, run whenever its outputs are older than its inputs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerateRule {
+    /// Command to run from the project directory. An argument of `{inputs}` or `{outputs}`
+    /// expands to the input or output paths, `{gen_dir}` is replaced by the directory outputs
+    /// are written to and `{tool:<name>}` by the executable of host tool target `<name>`.
+    pub command: Vec<String>,
+
+    /// Files the command reads, relative to the project directory
+    pub inputs: Vec<PathBuf>,
+
+    /// Files the command writes, relative to the target's generated files directory. Generated
+    /// sources are compiled with the target, and the directory is on its include path.
+    pub outputs: Vec<PathBuf>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Hash)]
 #[serde(default)]
 pub struct DependencyConfig {
@@ -1,5 +1,3 @@
-use std::io::Write;
-
 use anyhow::Result;
 
 use clap::Parser;
@@ -27,6 +25,10 @@ fn main() -> Result<()> {
         Command::Build { opts } => commands::build::build(&args, opts),
         Command::Clean { opts } => commands::clean::clean(&args, opts),
         Command::Run { build_opts } => commands::run::run(&args, build_opts),
+        Command::Test { build_opts } => commands::test::test(&args, build_opts),
+        Command::Coverage { opts } => commands::coverage::coverage(&args, opts),
+        Command::Pgo { opts } => commands::pgo::pgo(&args, opts),
+        Command::Why { file, build_opts } => commands::why::why(&args, file, build_opts),
     }?;
 
     Ok(())